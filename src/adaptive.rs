@@ -0,0 +1,152 @@
+use crate::decode::{prepare, represent, Opts};
+use crate::error::PcmError;
+
+// A generic adaptive DPCM without a step table: each byte's representation
+// value is a signed code that is scaled by the current step and added to the
+// previous sample. Codes in the upper half of the range grow the step by
+// `rate`, codes in the lowest eighth shrink it by the same factor, and the
+// step stays within min_step..=max_step.
+
+const GROW: i32 = 64;
+const SHRINK: i32 = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Adaptive {
+    pub rate: f64,
+    pub min_step: u16,
+    pub max_step: u16,
+}
+
+impl Default for Adaptive {
+    fn default() -> Adaptive {
+        Adaptive {
+            rate: 0.25,
+            min_step: 1,
+            max_step: 256,
+        }
+    }
+}
+
+impl std::fmt::Display for Adaptive {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{},{}", self.rate, self.min_step, self.max_step)
+    }
+}
+
+impl std::str::FromStr for Adaptive {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<Adaptive, PcmError> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let parsed = match parts[..] {
+            [rate, min, max] => rate
+                .parse::<f64>()
+                .ok()
+                .zip(min.parse::<u16>().ok())
+                .zip(max.parse::<u16>().ok()),
+            _ => return Err(PcmError::Invalid("expected rate,min,max".to_string())),
+        };
+        match parsed {
+            Some(((rate, min_step), max_step))
+                if rate >= 0.0 && 1 <= min_step && min_step <= max_step =>
+            {
+                Ok(Adaptive {
+                    rate,
+                    min_step,
+                    max_step,
+                })
+            }
+            _ => Err(PcmError::Invalid(format!(
+                "{:?}: rate must not be negative and 1 <= min <= max",
+                s
+            ))),
+        }
+    }
+}
+
+pub fn decode_adaptive(input: &[u8], opt: &Opts) -> Vec<i16> {
+    let a = opt.adaptive;
+    let data = input.get(opt.skip..).unwrap_or(&[]);
+    let mut out = Vec::with_capacity(data.len().div_ceil(opt.step));
+    let mut sample = 0i32;
+    let mut step = a.min_step as f64;
+    for &b in data.iter().step_by(opt.step) {
+        let code = (represent(prepare(b, opt), opt) as i32).clamp(-128, 127);
+        sample = (sample + code * step.round() as i32).clamp(i16::MIN as i32, i16::MAX as i32);
+        out.push(sample as i16);
+        if code.abs() >= GROW {
+            step *= 1.0 + a.rate;
+        } else if code.abs() < SHRINK {
+            step /= 1.0 + a.rate;
+        }
+        step = step.clamp(a.min_step as f64, a.max_step as f64);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::Representation;
+
+    // The step each code was scaled by, from the decoded differences.
+    fn steps(codes: &[i8], adaptive: Adaptive) -> Vec<i32> {
+        let opt = Opts {
+            representation: Representation::TwosComplement,
+            adaptive,
+            ..Opts::default()
+        };
+        let input: Vec<u8> = codes.iter().map(|&c| c as u8).collect();
+        let out = decode_adaptive(&input, &opt);
+        let mut prev = 0;
+        out.iter()
+            .zip(codes)
+            .map(|(&s, &code)| {
+                let step = (s as i32 - prev) / code as i32;
+                prev = s as i32;
+                step
+            })
+            .collect()
+    }
+
+    const LIMITS: Adaptive = Adaptive {
+        rate: 1.0,
+        min_step: 2,
+        max_step: 64,
+    };
+
+    #[test]
+    fn large_codes_grow_the_step_up_to_max() {
+        // alternating signs keep the sample away from the clamps
+        let codes: Vec<i8> = (0..8).map(|i| if i % 2 == 0 { 64 } else { -64 }).collect();
+        assert_eq!(steps(&codes, LIMITS), [2, 4, 8, 16, 32, 64, 64, 64]);
+    }
+
+    #[test]
+    fn small_codes_shrink_the_step_down_to_min() {
+        let mut codes = vec![64, -64, 64, -64, 64, -64];
+        codes.extend([1, -1, 1, -1, 15, -15, 1, -1]);
+        // the step adapts after each code, so the first small one still
+        // has the full step
+        assert_eq!(steps(&codes, LIMITS)[5..], [64, 64, 32, 16, 8, 4, 2, 2, 2]);
+    }
+
+    #[test]
+    fn middle_codes_hold_the_step() {
+        let codes = [64, -64, 16, -16, 63, -63, 1];
+        assert_eq!(steps(&codes, LIMITS), [2, 4, 8, 8, 8, 8, 8]);
+    }
+
+    #[test]
+    fn step_tracks_a_ramp() {
+        // a steep ramp needs full scale codes until the step, 1.25^n rounded
+        // from the default minimum of 1, catches up, and middle codes then
+        // hold it
+        let mut codes = vec![127; 6];
+        codes.extend([32; 4]);
+        assert_eq!(
+            steps(&codes, Adaptive::default()),
+            [1, 1, 2, 2, 2, 3, 4, 4, 4, 4]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+pub struct Stats {
+    pub rms: f64,
+    pub peak: i16,
+    pub dc: f64,
+    pub zcr: f64,
+}
+
+pub fn stats(samples: &[i16]) -> Stats {
+    let n = samples.len().max(1) as f64;
+    let sum: f64 = samples.iter().map(|&x| x as f64).sum();
+    let sum_sq: f64 = samples.iter().map(|&x| (x as f64) * (x as f64)).sum();
+    let peak = samples
+        .iter()
+        .map(|&x| x.saturating_abs())
+        .max()
+        .unwrap_or(0);
+    let crossings = samples
+        .windows(2)
+        .filter(|w| (w[0] < 0) != (w[1] < 0))
+        .count();
+    Stats {
+        rms: (sum_sq / n).sqrt(),
+        peak,
+        dc: sum / n,
+        zcr: crossings as f64 / n,
+    }
+}
+
+// Relative to a full-scale i16; silence comes out as negative infinity.
+pub fn dbfs(x: f64) -> f64 {
+    20.0 * (x / i16::MAX as f64).log10()
+}
+
+// Voiced sound and tones cross zero rarely compared to noise; very quiet
+// windows are reported as silence since their crossings are meaningless.
+pub fn classify(stats: &Stats, zcr_threshold: f64) -> &'static str {
+    if stats.rms < 64.0 {
+        "silence"
+    } else if stats.zcr < zcr_threshold {
+        "tonal/voiced"
+    } else {
+        "noise/unvoiced"
+    }
+}
+
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum WindowFunction {
+    Rect,
+    Hann,
+    Hamming,
+}
+
+fn window_weight(window: WindowFunction, i: usize, n: usize) -> f64 {
+    let phase = 2.0 * std::f64::consts::PI * i as f64 / (n.max(2) - 1) as f64;
+    match window {
+        WindowFunction::Rect => 1.0,
+        WindowFunction::Hann => 0.5 - 0.5 * phase.cos(),
+        WindowFunction::Hamming => 0.54 - 0.46 * phase.cos(),
+    }
+}
+
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f64::consts::PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// Magnitudes of the first half of the spectrum, zero-padding the samples to
+// the next power of two. Bin `i` is at `i * rate / (2 * result.len())` Hz.
+pub fn spectrum(samples: &[i16], window: WindowFunction) -> Vec<f64> {
+    let n = samples.len().next_power_of_two().max(2);
+    let mut re = vec![0.0; n];
+    let mut im = vec![0.0; n];
+    for (i, &x) in samples.iter().enumerate() {
+        re[i] = x as f64 * window_weight(window, i, samples.len());
+    }
+    fft(&mut re, &mut im);
+    (0..n / 2)
+        .map(|i| (re[i] * re[i] + im[i] * im[i]).sqrt() / n as f64)
+        .collect()
+}
+
+// Normalized so lag 0 is 1; the mean is removed first so DC does not look
+// like a long period.
+pub fn autocorrelation(samples: &[i16], max_lag: usize) -> Vec<f64> {
+    let n = samples.len().max(1) as f64;
+    let mean = samples.iter().map(|&x| x as f64).sum::<f64>() / n;
+    let x: Vec<f64> = samples.iter().map(|&s| s as f64 - mean).collect();
+    let energy: f64 = x.iter().map(|v| v * v).sum();
+    (0..=max_lag.min(x.len().saturating_sub(1)))
+        .map(|lag| {
+            if energy == 0.0 {
+                return 0.0;
+            }
+            x.iter().zip(&x[lag..]).map(|(a, b)| a * b).sum::<f64>() / energy
+        })
+        .collect()
+}
+
+// Period in samples of the strongest repetition. Taking the first peak
+// close to the best one avoids reporting a multiple of the true period.
+pub fn pitch_period(samples: &[i16]) -> Option<f64> {
+    let r = autocorrelation(samples, samples.len() / 2);
+    let start = r.iter().position(|&v| v < 0.0)?;
+    let best = r[start..].iter().cloned().fold(f64::MIN, f64::max);
+    if best < 0.3 {
+        return None;
+    }
+    let lag = (start..r.len() - 1)
+        .find(|&i| r[i] >= 0.9 * best && r[i] >= r[i - 1] && r[i] >= r[i + 1])?;
+    // parabolic interpolation between the neighbouring lags
+    let (a, b, c) = (r[lag - 1], r[lag], r[lag + 1]);
+    let denom = a - 2.0 * b + c;
+    let offset = if denom == 0.0 {
+        0.0
+    } else {
+        0.5 * (a - c) / denom
+    };
+    Some(lag as f64 + offset)
+}
+
+pub const COMMON_RATES: [u32; 10] = [
+    4000, 8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000,
+];
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+// Nearest equal-tempered note (A4 = 440 Hz), its frequency, and how far
+// off freq is in cents.
+pub fn nearest_note(freq: f64) -> (String, f64, f64) {
+    let midi = 69.0 + 12.0 * (freq / 440.0).log2();
+    let note = midi.round();
+    let name = format!(
+        "{}{}",
+        NOTE_NAMES[(note as i64).rem_euclid(12) as usize],
+        (note as i64).div_euclid(12) - 1
+    );
+    let note_freq = 440.0 * 2f64.powf((note - 69.0) / 12.0);
+    (name, note_freq, (midi - note) * 100.0)
+}
+
+pub struct Run {
+    pub start: usize,
+    pub len: usize,
+    pub byte: u8,
+}
+
+// Runs of one repeated byte at least min_len long, longest first.
+pub fn runs(data: &[u8], min_len: usize) -> Vec<Run> {
+    let mut runs = vec![];
+    let mut start = 0;
+    for i in 1..=data.len() {
+        if i == data.len() || data[i] != data[start] {
+            if i - start >= min_len {
+                runs.push(Run {
+                    start,
+                    len: i - start,
+                    byte: data[start],
+                });
+            }
+            start = i;
+        }
+    }
+    runs.sort_by(|a, b| b.len.cmp(&a.len).then(a.start.cmp(&b.start)));
+    runs
+}
+
+// The sounds between gaps of at least min_gap samples below threshold, the
+// gate's test for silence, as (start, len). Gaps shorter than that, such as
+// zero crossings, stay inside a sound.
+pub fn sounds(out: &[i16], threshold: u16, min_gap: usize) -> Vec<(usize, usize)> {
+    let loud = |x: i16| (x as i32).abs() >= threshold as i32;
+    let mut sounds = vec![];
+    let mut current: Option<(usize, usize)> = None;
+    for (i, &x) in out.iter().enumerate() {
+        if !loud(x) {
+            continue;
+        }
+        current = match current {
+            Some((start, last)) if i - last <= min_gap => Some((start, i)),
+            Some((start, last)) => {
+                sounds.push((start, last + 1 - start));
+                Some((i, i))
+            }
+            None => Some((i, i)),
+        };
+    }
+    if let Some((start, last)) = current {
+        sounds.push((start, last + 1 - start));
+    }
+    sounds
+}
+
+// Mean step between neighbouring samples relative to their RMS. Sound moves
+// in small steps compared to its level while a wrong reading of the same
+// bytes jumps around like noise, so lower is more plausible. None for
+// silence.
+pub fn roughness(samples: &[i16]) -> Option<f64> {
+    let rms = stats(samples).rms;
+    if samples.len() < 2 || rms == 0.0 {
+        return None;
+    }
+    let steps: f64 = samples
+        .windows(2)
+        .map(|w| (w[1] as f64 - w[0] as f64).abs())
+        .sum();
+    Some(steps / (samples.len() - 1) as f64 / rms)
+}
+
+// White noise scores about 1.13; anything this rough is no plain PCM.
+pub const NOISE_ROUGHNESS: f64 = 1.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Depth {
+    Signed8,
+    Unsigned8,
+    Little16,
+    Big16,
+}
+
+impl Depth {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Depth::Signed8 => "8-bit signed",
+            Depth::Unsigned8 => "8-bit unsigned",
+            Depth::Little16 => "16-bit little-endian",
+            Depth::Big16 => "16-bit big-endian",
+        }
+    }
+
+    pub fn read(&self, data: &[u8]) -> Vec<i16> {
+        match self {
+            Depth::Signed8 => data.iter().map(|&b| (b as i8 as i16) << 8).collect(),
+            Depth::Unsigned8 => data.iter().map(|&b| (b as i16 - 128) << 8).collect(),
+            Depth::Little16 => data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+            Depth::Big16 => data
+                .chunks_exact(2)
+                .map(|b| i16::from_be_bytes([b[0], b[1]]))
+                .collect(),
+        }
+    }
+}
+
+// Roughness of each plain PCM reading of the bytes, smoothest first.
+pub fn detect_depth(data: &[u8]) -> Vec<(Depth, Option<f64>)> {
+    let mut scores: Vec<_> = [
+        Depth::Signed8,
+        Depth::Unsigned8,
+        Depth::Little16,
+        Depth::Big16,
+    ]
+    .into_iter()
+    .map(|depth| (depth, roughness(&depth.read(data))))
+    .collect();
+    scores.sort_by(|a, b| {
+        let a = a.1.unwrap_or(f64::INFINITY);
+        let b = b.1.unwrap_or(f64::INFINITY);
+        a.total_cmp(&b)
+    });
+    scores
+}
@@ -0,0 +1,150 @@
+use crate::decode::{bad_blocks, channels, decode, decoded_len, Opts};
+use crate::error::{PcmError, Result};
+use crate::export;
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_TEMPLATE: &str = "{stem}.{format}";
+
+pub fn expand_template(template: &str, input: &Path, opts: &Opts) -> Result<String> {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => {
+                return Err(PcmError::Invalid(format!(
+                    "unterminated placeholder in template '{}'",
+                    template
+                )))
+            }
+        };
+        let value = match &rest[start + 1..end] {
+            "stem" => stem.to_string(),
+            "ext" => input
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            "format" => opts.file_format.to_string(),
+            "rate" => opts.rate.to_string(),
+            "representation" => opts.representation.to_string(),
+            "compression" => opts.compression.to_string(),
+            "k" => opts.k.to_string(),
+            "flip" => opts.flip.to_string(),
+            "mirror" => opts.mirror.to_string(),
+            "sign" => opts.sign.to_string(),
+            "step" => opts.step.to_string(),
+            "skip" => opts.skip.to_string(),
+            other => {
+                return Err(PcmError::Invalid(format!(
+                    "unknown placeholder '{{{}}}' in template '{}' (known: stem, ext, format, \
+                     rate, representation, compression, k, flip, mirror, sign, step, skip)",
+                    other, template
+                )))
+            }
+        };
+        name.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+    Ok(name)
+}
+
+fn convert(input: &Path, output: &Path, opts: &Opts) -> Result<String> {
+    let data =
+        fs::read(input).map_err(PcmError::io(format!("failed to read {}", input.display())))?;
+    let out = decode(&data, opts);
+    let summary = export::export(output, &out, opts)?;
+    match bad_blocks(&data, opts).len() {
+        0 => Ok(summary),
+        n => Ok(format!("{}, {} invalid blocks", summary, n)),
+    }
+}
+
+pub fn list_dir(in_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(in_dir)
+        .map_err(PcmError::io(format!(
+            "failed to read directory {}",
+            in_dir.display()
+        )))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    inputs.sort();
+    Ok(inputs)
+}
+
+pub fn run(
+    inputs: &[PathBuf],
+    out_dir: &Path,
+    template: &str,
+    opts: &Opts,
+    dry_run: bool,
+) -> Result<()> {
+    let outputs = inputs
+        .iter()
+        .map(|input| Ok(out_dir.join(expand_template(template, input, opts)?)))
+        .collect::<Result<Vec<_>>>()?;
+    for (i, output) in outputs.iter().enumerate() {
+        if outputs[..i].contains(output) {
+            return Err(PcmError::Invalid(format!(
+                "template '{}' maps several inputs to {}",
+                template,
+                output.display()
+            )));
+        }
+    }
+    if dry_run {
+        for (input, output) in inputs.iter().zip(&outputs) {
+            let len = fs::metadata(input)
+                .map_err(PcmError::io(format!("failed to read {}", input.display())))?
+                .len();
+            let samples = decoded_len(len as usize, opts) / channels(opts);
+            println!(
+                "{} -> {} ({} samples, {:.2}s)",
+                input.display(),
+                output.display(),
+                samples,
+                samples as f64 / opts.rate as f64
+            );
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(out_dir).map_err(PcmError::io(format!(
+        "failed to create directory {}",
+        out_dir.display()
+    )))?;
+
+    let results: Vec<_> = inputs
+        .par_iter()
+        .zip(outputs.par_iter())
+        .map(|(input, output)| (input, convert(input, output, opts)))
+        .collect();
+
+    let mut failed = 0;
+    for (input, res) in &results {
+        match res {
+            Ok(summary) => println!("ok: {} -> {}", input.display(), summary),
+            Err(err) => {
+                failed += 1;
+                eprintln!("failed: {}: {:#}", input.display(), err);
+            }
+        }
+    }
+    println!(
+        "{} of {} files converted",
+        results.len() - failed,
+        results.len()
+    );
+    if failed > 0 {
+        return Err(PcmError::Invalid(format!(
+            "{} files failed to convert",
+            failed
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,468 @@
+use crate::decode::{prepare, Compression, NibbleOrder, OnError, Opts, Partial};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MarkerKind {
+    LoopStart,
+    LoopEnd,
+    End,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Marker {
+    pub index: usize,
+    pub kind: MarkerKind,
+}
+
+// Header bytes and canonical block size in bytes.
+fn layout(compression: Compression) -> Option<(usize, usize)> {
+    match compression {
+        Compression::BRR => Some((1, 9)),
+        Compression::PSXADPCM => Some((2, 16)),
+        Compression::AppleIMA4 => Some((2, 34)),
+        _ => None,
+    }
+}
+
+// Block size in bytes and decoded samples per block. A frame size replaces
+// the canonical size, keeping at least one data byte after the header.
+pub fn block_format(opt: &Opts) -> Option<(usize, usize)> {
+    let (header, size) = layout(opt.compression)?;
+    let size = opt.frame_size.unwrap_or(size).max(header + 1);
+    Some((size, (size - header) * 2))
+}
+
+fn sign_extend(nibble: u8) -> i32 {
+    ((nibble << 4) as i8 >> 4) as i32
+}
+
+fn clamp16(s: i32) -> i32 {
+    s.clamp(i16::MIN as i32, i16::MAX as i32)
+}
+
+// SNES: a header byte (shift, filter, loop and end flags) and 8 data bytes.
+fn brr_block(
+    block: &[u8],
+    opt: &Opts,
+    hist: &mut [i32; 2],
+    out: &mut Vec<i16>,
+    markers: &mut Vec<Marker>,
+) {
+    let header = block[0];
+    let shift = header >> 4;
+    let filter = (header >> 2) & 3;
+    let end = out.len() + (block.len() - 1) * 2;
+    if header & 1 != 0 {
+        if header & 2 != 0 {
+            markers.push(Marker {
+                index: end,
+                kind: MarkerKind::LoopEnd,
+            });
+        }
+        markers.push(Marker {
+            index: end,
+            kind: MarkerKind::End,
+        });
+    }
+    for &b in &block[1..] {
+        for nibble in opt.nibble_order.split(NibbleOrder::High, b) {
+            let mut s = sign_extend(nibble);
+            s = if shift <= 12 {
+                (s << shift) >> 1
+            } else if s < 0 {
+                -2048
+            } else {
+                0
+            };
+            let [p1, p2] = *hist;
+            s += match filter {
+                0 => 0,
+                1 => p1 + ((-p1) >> 4),
+                2 => (p1 * 2) + ((-p1 * 3) >> 5) - p2 + (p2 >> 4),
+                _ => (p1 * 2) + ((-p1 * 13) >> 6) - p2 + ((p2 * 3) >> 4),
+            };
+            // the DSP keeps 15 bits of clamped history
+            let s = ((clamp16(s) as i16) << 1) >> 1;
+            *hist = [s as i32, p1];
+            out.push(s.saturating_mul(2));
+        }
+    }
+}
+
+const PSX_POS: [i32; 5] = [0, 60, 115, 98, 122];
+const PSX_NEG: [i32; 5] = [0, 0, -52, -55, -60];
+
+// PlayStation SPU ADPCM (also used inside CD-XA): a shift/filter byte, a
+// flags byte (bit 0 loop end, bit 1 repeat, bit 2 loop start) and 14 data
+// bytes.
+fn psx_block(
+    block: &[u8],
+    opt: &Opts,
+    hist: &mut [i32; 2],
+    out: &mut Vec<i16>,
+    markers: &mut Vec<Marker>,
+) {
+    let shift = match block[0] & 0xF {
+        s if s > 12 => 9,
+        s => s,
+    };
+    let filter = ((block[0] >> 4) as usize).min(4);
+    let flags = block[1];
+    if flags & 4 != 0 {
+        markers.push(Marker {
+            index: out.len(),
+            kind: MarkerKind::LoopStart,
+        });
+    }
+    if flags & 1 != 0 {
+        markers.push(Marker {
+            index: out.len() + (block.len() - 2) * 2,
+            kind: if flags & 2 != 0 {
+                MarkerKind::LoopEnd
+            } else {
+                MarkerKind::End
+            },
+        });
+    }
+    for &b in &block[2..] {
+        for nibble in opt.nibble_order.split(NibbleOrder::Low, b) {
+            let [p1, p2] = *hist;
+            let s = ((sign_extend(nibble) << 12) >> shift)
+                + ((p1 * PSX_POS[filter] + p2 * PSX_NEG[filter] + 32) >> 6);
+            let s = clamp16(s);
+            *hist = [s, p1];
+            out.push(s as i16);
+        }
+    }
+}
+
+const IMA_INDEX: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+const IMA_STEP: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+// QuickTime IMA4: a big-endian preamble holding the predictor in its top 9
+// bits and the step index in the low 7, then 32 data bytes, low nibble
+// first. The preamble is a truncated copy of the encoder state, so like
+// QuickTime the full predictor of the previous block is kept while it
+// agrees with the preamble.
+fn ima4_block(block: &[u8], opt: &Opts, state: &mut [i32; 2], out: &mut Vec<i16>) {
+    let preamble = u16::from_be_bytes([block[0], block[1]]);
+    let start = (preamble & 0xFF80) as i16 as i32;
+    let [mut predictor, mut index] = *state;
+    if index != (preamble & 0x7F) as i32 || (predictor - start).abs() > 0x7F {
+        predictor = start;
+        index = (preamble & 0x7F) as i32;
+    }
+    index = index.min(88);
+    for &b in &block[2..] {
+        for nibble in opt.nibble_order.split(NibbleOrder::Low, b) {
+            let step = IMA_STEP[index as usize];
+            let mut diff = step >> 3;
+            if nibble & 4 != 0 {
+                diff += step;
+            }
+            if nibble & 2 != 0 {
+                diff += step >> 1;
+            }
+            if nibble & 1 != 0 {
+                diff += step >> 2;
+            }
+            if nibble & 8 != 0 {
+                diff = -diff;
+            }
+            predictor = clamp16(predictor + diff);
+            index = (index + IMA_INDEX[nibble as usize]).clamp(0, 88);
+            out.push(predictor as i16);
+        }
+    }
+    *state = [predictor, index];
+}
+
+// Whether a header holds values the format leaves undefined: a shift past 12,
+// a PSX filter past 4, an IMA step index past 88.
+fn invalid(block: &[u8], compression: Compression) -> bool {
+    match compression {
+        Compression::BRR => block[0] >> 4 > 12,
+        Compression::AppleIMA4 => block[1] & 0x7F > 88,
+        _ => block[0] & 0xF > 12 || block[0] >> 4 > 4,
+    }
+}
+
+// Decodes whole blocks, recording loop markers. Blocks with invalid headers
+// are decoded clamped to the nearest valid values, replaced by silence or end
+// the decode, depending on on_error.
+pub fn decode_blocks(input: &[u8], opt: &Opts, markers: &mut Vec<Marker>) -> Vec<i16> {
+    let (size, samples) = match block_format(opt) {
+        Some(format) => format,
+        None => return vec![],
+    };
+    let mut out = vec![];
+    let mut hist = [0; 2];
+    if opt.skip >= input.len() {
+        return out;
+    }
+    let blocks = input[opt.skip..].chunks(size);
+    out.reserve(blocks.len() * samples);
+    let mut buf = vec![0u8; size];
+    for (i, block) in blocks.enumerate() {
+        if block.len() < size && opt.partial == Partial::Drop {
+            break;
+        }
+        // a padded block reads zero bytes past the end
+        buf.fill(0);
+        for (d, &b) in buf.iter_mut().zip(block) {
+            *d = prepare(b, opt);
+        }
+        // Native blocks keep their history: BRR and PSX filters predict the
+        // first samples of a block from the last two of the one before, as
+        // the hardware does, and resetting there would click at every
+        // block. IMA4 restarts from its preamble either way. Blocks of a
+        // frame size set by hand are decoded as independent frames instead,
+        // so a wrong guess does not carry over.
+        if opt.frame_size.is_some() {
+            hist = [0; 2];
+        }
+        let block = &buf[..];
+        if invalid(block, opt.compression) {
+            let offset = opt.skip + i * size;
+            log::warn!("invalid {} block at offset {:#x}", opt.compression, offset);
+            match opt.on_error {
+                OnError::Clamp => {}
+                OnError::Skip => {
+                    out.resize(out.len() + samples, 0);
+                    hist = [0; 2];
+                    continue;
+                }
+                OnError::Stop => break,
+            }
+        }
+        match opt.compression {
+            Compression::BRR => brr_block(block, opt, &mut hist, &mut out, markers),
+            Compression::AppleIMA4 => ima4_block(block, opt, &mut hist, &mut out),
+            _ => psx_block(block, opt, &mut hist, &mut out, markers),
+        }
+    }
+    out
+}
+
+// Byte offsets of the blocks decode_blocks finds invalid, read from their
+// headers alone. With on_error stop the list ends at the block the decode
+// stops at.
+pub fn bad_blocks(input: &[u8], opt: &Opts) -> Vec<usize> {
+    let (size, header_len) = match (block_format(opt), layout(opt.compression)) {
+        (Some((size, _)), Some((header_len, _))) => (size, header_len),
+        _ => return vec![],
+    };
+    let data = input.get(opt.skip..).unwrap_or(&[]);
+    let mut bad = vec![];
+    for (i, block) in data.chunks(size).enumerate() {
+        if block.len() < size && opt.partial == Partial::Drop {
+            break;
+        }
+        let mut header = [0u8; 2];
+        for (d, &b) in header.iter_mut().zip(&block[..header_len.min(block.len())]) {
+            *d = prepare(b, opt);
+        }
+        if invalid(&header, opt.compression) {
+            bad.push(opt.skip + i * size);
+            if opt.on_error == OnError::Stop {
+                break;
+            }
+        }
+    }
+    bad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(compression: Compression) -> Opts {
+        Opts {
+            compression,
+            ..Default::default()
+        }
+    }
+
+    fn decode(input: &[u8], opt: &Opts) -> Vec<i16> {
+        decode_blocks(input, opt, &mut vec![])
+    }
+
+    fn block(header: &[u8], data: &[u8], size: usize) -> Vec<u8> {
+        let mut block = [header, data].concat();
+        block.resize(size, 0);
+        block
+    }
+
+    #[test]
+    fn brr_shifts_nibbles_high_first() {
+        let out = decode(&block(&[0xC0], &[0x17, 0xF8], 9), &opt(Compression::BRR));
+        assert_eq!(out.len(), 16);
+        assert_eq!(out[..4], [4096, 28672, -4096, -32768]);
+        assert!(out[4..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn brr_filter_history_keeps_15_bits() {
+        // 14336 + 14336 - 896 = 27776 wraps to -4992 in the 15-bit history
+        let out = decode(&block(&[0xC4], &[0x77, 0x70], 9), &opt(Compression::BRR));
+        assert_eq!(out[..4], [28672, -9984, 19312, 18104]);
+    }
+
+    #[test]
+    fn brr_invalid_shift_follows_on_error() {
+        let input = [block(&[0xD0], &[0x8F, 0x10], 9), block(&[0xC0], &[0x10], 9)].concat();
+        let mut o = opt(Compression::BRR);
+        let out = decode(&input, &o);
+        assert_eq!(out[..4], [-4096, -4096, 0, 0]);
+        assert_eq!(out[16], 4096);
+        o.on_error = OnError::Skip;
+        let out = decode(&input, &o);
+        assert!(out[..16].iter().all(|&s| s == 0));
+        assert_eq!(out[16], 4096);
+        o.on_error = OnError::Stop;
+        assert!(decode(&input, &o).is_empty());
+        assert_eq!(bad_blocks(&input, &o), [0]);
+    }
+
+    #[test]
+    fn brr_end_flags_mark_the_block_end() {
+        let input = [block(&[0xC0], &[], 9), block(&[0xC3], &[], 9)].concat();
+        let mut markers = vec![];
+        decode_blocks(&input, &opt(Compression::BRR), &mut markers);
+        assert_eq!(
+            markers,
+            [
+                Marker {
+                    index: 32,
+                    kind: MarkerKind::LoopEnd
+                },
+                Marker {
+                    index: 32,
+                    kind: MarkerKind::End
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn psx_shifts_nibbles_low_first() {
+        let out = decode(
+            &block(&[0x00, 0x00], &[0x71, 0x8F], 16),
+            &opt(Compression::PSXADPCM),
+        );
+        assert_eq!(out.len(), 28);
+        assert_eq!(out[..4], [4096, 28672, -4096, -32768]);
+    }
+
+    #[test]
+    fn psx_filters_predict_from_history() {
+        let o = opt(Compression::PSXADPCM);
+        // (1792 * 60 + 32) >> 6 = 1680, and so on
+        let out = decode(&block(&[0x14, 0x00], &[0x07], 16), &o);
+        assert_eq!(out[..4], [1792, 1680, 1575, 1477]);
+        // (1680 * 115 - 1792 * 52 + 32) >> 6 = 4330
+        let out = decode(&block(&[0x24, 0x00], &[0x07], 16), &o);
+        assert_eq!(out[..3], [1792, 3220, 4330]);
+    }
+
+    #[test]
+    fn psx_shift_past_12_decodes_as_9() {
+        let input = block(&[0x0D, 0x00], &[0x01], 16);
+        let o = opt(Compression::PSXADPCM);
+        assert_eq!(decode(&input, &o)[0], 8);
+        assert_eq!(bad_blocks(&input, &o), [0]);
+    }
+
+    #[test]
+    fn psx_flags_mark_loops() {
+        let input = [
+            block(&[0, 4], &[], 16),
+            block(&[0, 3], &[], 16),
+            block(&[0, 1], &[], 16),
+        ]
+        .concat();
+        let mut markers = vec![];
+        decode_blocks(&input, &opt(Compression::PSXADPCM), &mut markers);
+        let expected = [
+            (0, MarkerKind::LoopStart),
+            (56, MarkerKind::LoopEnd),
+            (84, MarkerKind::End),
+        ]
+        .map(|(index, kind)| Marker { index, kind });
+        assert_eq!(markers, expected);
+    }
+
+    #[test]
+    fn ima4_steps_from_the_preamble() {
+        let o = opt(Compression::AppleIMA4);
+        let out = decode(&block(&[0x00, 0x00], &[0x74, 0x08], 34), &o);
+        assert_eq!(out.len(), 64);
+        assert_eq!(out[..4], [7, 23, 21, 23]);
+        assert_eq!(decode(&block(&[0x12, 0x80], &[], 34), &o)[0], 4736);
+        assert_eq!(decode(&block(&[0xFF, 0x80], &[], 34), &o)[0], -128);
+    }
+
+    #[test]
+    fn ima4_keeps_the_predictor_while_the_preamble_agrees() {
+        let o = opt(Compression::AppleIMA4);
+        // the first block ends at 9 with step index 0
+        let first = block(&[0x00, 0x00], &[0x04], 34);
+        let out = decode(
+            &[first.clone(), block(&[0x00, 0x00], &[0x04], 34)].concat(),
+            &o,
+        );
+        assert_eq!(out[..3], [7, 8, 9]);
+        assert_eq!(out[63], 9);
+        assert_eq!(out[64], 16);
+        let out = decode(&[first, block(&[0x01, 0x00], &[0x04], 34)].concat(), &o);
+        assert_eq!(out[64], 263);
+    }
+
+    #[test]
+    fn partial_blocks_drop_or_pad() {
+        // 20 bytes: one PSX block and 4 bytes of the next
+        let input = block(&[0x00, 0x00], &[0x71], 20);
+        let mut o = opt(Compression::PSXADPCM);
+        assert_eq!(decode(&input, &o).len(), 28);
+        o.partial = Partial::Pad;
+        let out = decode(&input, &o);
+        assert_eq!(out.len(), 56);
+        assert_eq!(out[..2], [4096, 28672]);
+        // the padded block decodes its zero bytes as silence
+        assert!(out[28..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn a_partial_header_is_padded_with_zeros() {
+        // one IMA4 block and a lone byte of the preamble, 0x01 then 0x00
+        let mut input = block(&[0x00, 0x00], &[0x04], 34);
+        input.push(0x01);
+        let mut o = opt(Compression::AppleIMA4);
+        assert_eq!(decode(&input, &o).len(), 64);
+        assert!(bad_blocks(&input, &o).is_empty());
+        o.partial = Partial::Pad;
+        let out = decode(&input, &o);
+        assert_eq!(out.len(), 128);
+        assert_eq!(out[64], 256);
+    }
+
+    #[test]
+    fn only_set_frame_sizes_reset_the_history() {
+        // the first block ends on 2048, which filter 1 carries into the
+        // second: 2048 - (2048 >> 4) = 1920
+        let input = [
+            block(&[0xC0], &[0, 0, 0, 0, 0, 0, 0, 0x01], 9),
+            block(&[0xC4], &[], 9),
+        ]
+        .concat();
+        let mut o = opt(Compression::BRR);
+        assert_eq!(decode(&input, &o)[16], 3840);
+        o.frame_size = Some(9);
+        assert_eq!(decode(&input, &o)[16], 0);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::decode::{decode, Opts};
+use crate::error::{PcmError, Result};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// FNV-1a, so keys stay the same across builds.
+fn fnv1a(data: &[u8], mut hash: u64) -> u64 {
+    for &b in data {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn hash_input(input: &[u8]) -> u64 {
+    fnv1a(input, 0xcbf29ce484222325)
+}
+
+// Everything that changes what decode returns: the settings with the view,
+// the export layout and the selected channel reset, so moving around reuses
+// the same entry and any new decode setting is part of the key by default.
+fn key(input_hash: u64, opt: &Opts) -> String {
+    let defaults = Opts::default();
+    let decoding = Opts {
+        from: 0,
+        to: 0,
+        zcr_threshold: 0.0,
+        windowed: false,
+        max_samples: None,
+        downmix: false,
+        upmix: false,
+        oversample: 1,
+        sample_format: defaults.sample_format,
+        file_format: defaults.file_format,
+        dither: defaults.dither,
+        embed_markers: false,
+        channel: 0,
+        ..opt.clone()
+    };
+    format!("input={:016x} {:?}", input_hash, decoding)
+}
+
+// Each entry holds its full key on the first line, then the samples as
+// little-endian i16.
+fn path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!(
+        "{:016x}.pcm",
+        fnv1a(key.as_bytes(), 0xcbf29ce484222325)
+    ))
+}
+
+fn load(path: &Path, key: &str) -> Option<Vec<i16>> {
+    let mut file = BufReader::new(fs::File::open(path).ok()?);
+    let mut header = String::new();
+    file.read_line(&mut header).ok()?;
+    if header.trim_end() != key {
+        return None;
+    }
+    let mut data = vec![];
+    file.read_to_end(&mut data).ok()?;
+    Some(
+        data.chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect(),
+    )
+}
+
+fn store(path: &Path, key: &str, out: &[i16]) -> Result<()> {
+    let mut file = BufWriter::new(
+        fs::File::create(path)
+            .map_err(PcmError::io(format!("failed to create {}", path.display())))?,
+    );
+    let write_err = || PcmError::io(format!("failed to write {}", path.display()));
+    writeln!(file, "{}", key).map_err(write_err())?;
+    for x in out {
+        file.write_all(&x.to_le_bytes()).map_err(write_err())?;
+    }
+    file.flush().map_err(write_err())
+}
+
+pub fn decode_cached(dir: &Path, input_hash: u64, input: &[u8], opt: &Opts) -> Vec<i16> {
+    let key = key(input_hash, opt);
+    let path = path(dir, &key);
+    if let Some(out) = load(&path, &key) {
+        log::debug!("cache hit {}", path.display());
+        return out;
+    }
+    let out = decode(input, opt);
+    if let Err(e) = fs::create_dir_all(dir)
+        .map_err(PcmError::io(format!("failed to create {}", dir.display())))
+        .and_then(|_| store(&path, &key, &out))
+    {
+        eprintln!("cache: {:#}", e);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_ignores_the_view_and_export_layout() {
+        let opt = Opts::default();
+        let moved = Opts {
+            from: 100,
+            to: 200,
+            windowed: true,
+            oversample: 4,
+            upmix: true,
+            channel: 1,
+            ..opt.clone()
+        };
+        assert_eq!(key(1, &opt), key(1, &moved));
+        assert!(!key(1, &opt).contains('\n'));
+    }
+
+    #[test]
+    fn key_follows_every_decode_setting() {
+        let opt = Opts::default();
+        for changed in [
+            Opts {
+                step: 2,
+                ..opt.clone()
+            },
+            Opts {
+                partial: crate::decode::Partial::Pad,
+                ..opt.clone()
+            },
+            Opts {
+                plane_boundary: Some(10),
+                ..opt.clone()
+            },
+            Opts {
+                table: vec![1; 16],
+                ..opt.clone()
+            },
+        ] {
+            assert_ne!(key(1, &opt), key(1, &changed));
+        }
+        assert_ne!(key(1, &opt), key(2, &opt));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::decode::{prepare, NibbleOrder, Opts};
+
+// Sound Blaster ADPCM: a leading reference byte, then 4-, 2.6- (3) or 2-bit
+// codes. Each code plus the running step index selects a delta for the
+// unsigned 8-bit reference and an adjustment of the step index.
+
+const SCALE_4: [i32; 64] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 0, -1, -2, -3, -4, -5, -6, -7, //
+    1, 3, 5, 7, 9, 11, 13, 15, -1, -3, -5, -7, -9, -11, -13, -15, //
+    2, 6, 10, 14, 18, 22, 26, 30, -2, -6, -10, -14, -18, -22, -26, -30, //
+    4, 12, 20, 28, 36, 44, 52, 60, -4, -12, -20, -28, -36, -44, -52, -60,
+];
+const ADJUST_4: [u8; 64] = [
+    0, 0, 0, 0, 0, 16, 16, 16, 0, 0, 0, 0, 0, 16, 16, 16, //
+    240, 0, 0, 0, 0, 16, 16, 16, 240, 0, 0, 0, 0, 16, 16, 16, //
+    240, 0, 0, 0, 0, 16, 16, 16, 240, 0, 0, 0, 0, 16, 16, 16, //
+    240, 0, 0, 0, 0, 0, 0, 0, 240, 0, 0, 0, 0, 0, 0, 0,
+];
+
+const SCALE_3: [i32; 40] = [
+    0, 1, 2, 3, 0, -1, -2, -3, //
+    1, 3, 5, 7, -1, -3, -5, -7, //
+    2, 6, 10, 14, -2, -6, -10, -14, //
+    4, 12, 20, 28, -4, -12, -20, -28, //
+    5, 15, 25, 35, -5, -15, -25, -35,
+];
+const ADJUST_3: [u8; 40] = [
+    0, 0, 0, 8, 0, 0, 0, 8, //
+    248, 0, 0, 8, 248, 0, 0, 8, //
+    248, 0, 0, 8, 248, 0, 0, 8, //
+    248, 0, 0, 8, 248, 0, 0, 8, //
+    248, 0, 0, 0, 248, 0, 0, 0,
+];
+
+const SCALE_2: [i32; 24] = [
+    0, 1, 0, -1, 1, 3, -1, -3, 2, 6, -2, -6, //
+    4, 12, -4, -12, 8, 24, -8, -24, 16, 48, -16, -48,
+];
+const ADJUST_2: [u8; 24] = [
+    0, 4, 0, 4, 252, 4, 252, 4, 252, 4, 252, 4, //
+    252, 4, 252, 4, 252, 4, 252, 4, 252, 0, 252, 0,
+];
+
+pub fn samples_per_byte(bits: u8) -> usize {
+    match bits {
+        2 => 4,
+        3 => 3,
+        _ => 2,
+    }
+}
+
+struct State {
+    reference: u8,
+    step: u8,
+}
+
+impl State {
+    fn next(&mut self, code: u8, scale: &[i32], adjust: &[u8]) -> i16 {
+        let ix = (code as usize + self.step as usize).min(scale.len() - 1);
+        self.reference = (self.reference as i32 + scale[ix]).clamp(0, 255) as u8;
+        self.step = self.step.wrapping_add(adjust[ix]);
+        (self.reference as i16 - 128) << 8
+    }
+}
+
+pub fn decode_creative(input: &[u8], opt: &Opts) -> Vec<i16> {
+    let data = match input.get(opt.skip..) {
+        Some(data) if !data.is_empty() => data,
+        _ => return vec![],
+    };
+    let mut state = State {
+        reference: prepare(data[0], opt),
+        step: 0,
+    };
+    let mut out = Vec::with_capacity((data.len() - 1) * samples_per_byte(opt.creative_bits));
+    for &b in &data[1..] {
+        let b = prepare(b, opt);
+        match opt.creative_bits {
+            2 => {
+                for shift in [6, 4, 2, 0] {
+                    out.push(state.next((b >> shift) & 3, &SCALE_2, &ADJUST_2));
+                }
+            }
+            3 => {
+                for code in [(b >> 5) & 7, (b >> 2) & 7, (b & 3) << 1] {
+                    out.push(state.next(code, &SCALE_3, &ADJUST_3));
+                }
+            }
+            _ => {
+                for code in opt.nibble_order.split(NibbleOrder::High, b) {
+                    out.push(state.next(code, &SCALE_4, &ADJUST_4));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{decode, decoded_len, Compression};
+
+    fn opt(bits: u8) -> Opts {
+        Opts {
+            compression: Compression::CreativeADPCM,
+            creative_bits: bits,
+            ..Opts::default()
+        }
+    }
+
+    // Worked through the tables by hand: each sample is the new reference,
+    // recentred and scaled to 16 bits.
+    #[test]
+    fn four_bit_codes() {
+        // codes 3, 7, 15, 9 from reference 128: +3, +7 (step up), -15 at
+        // step 16 (step up), -6 at step 32
+        let input = [0x80, 0x37, 0xF9];
+        assert_eq!(
+            decode_creative(&input, &opt(4)),
+            [3 << 8, 10 << 8, -5 << 8, -11 << 8]
+        );
+        let low = Opts {
+            nibble_order: NibbleOrder::Low,
+            ..opt(4)
+        };
+        assert_eq!(decode_creative(&[0x80, 0x73], &low), [3 << 8, 10 << 8]);
+    }
+
+    #[test]
+    fn two_and_a_half_bit_codes() {
+        // codes 3, 7 and the last two bits doubled to 2, from reference 128
+        let input = [0x80, 3 << 5 | 7 << 2 | 1];
+        assert_eq!(decode_creative(&input, &opt(3)), [3 << 8, -4 << 8, 6 << 8]);
+    }
+
+    #[test]
+    fn two_bit_codes() {
+        // codes 1, 1, 3, 0 from reference 64; the last one steps back down
+        let input = [0x40, 1 << 6 | 1 << 4 | 3 << 2];
+        assert_eq!(
+            decode_creative(&input, &opt(2)),
+            [-63 << 8, -60 << 8, -66 << 8, -62 << 8]
+        );
+    }
+
+    #[test]
+    fn reference_byte_starts_the_decode() {
+        // the reference itself produces no sample
+        assert_eq!(decode_creative(&[0x80], &opt(4)), []);
+        assert_eq!(decode_creative(&[], &opt(4)), []);
+        // and the predictor saturates at the ends of its 8-bit range; code 0
+        // after the step went up still adds one
+        assert_eq!(
+            decode_creative(&[0xFF, 0x70], &opt(4)),
+            [127 << 8, 127 << 8]
+        );
+        assert_eq!(
+            decode_creative(&[0x00, 0xF0], &opt(4)),
+            [-128 << 8, -127 << 8]
+        );
+        // skip moves the reference byte with it
+        let skip = Opts { skip: 1, ..opt(4) };
+        assert_eq!(
+            decode_creative(&[0x00, 0x80, 0x37], &skip),
+            [3 << 8, 10 << 8]
+        );
+    }
+
+    #[test]
+    fn lengths_follow_the_code_size() {
+        for bits in [2, 3, 4] {
+            let input = [0x80; 5];
+            let out = decode(&input, &opt(bits));
+            assert_eq!(out.len(), 4 * samples_per_byte(bits));
+            assert_eq!(decoded_len(input.len(), &opt(bits)), out.len());
+        }
+    }
+}
@@ -0,0 +1,1493 @@
+use crate::adaptive::{self, Adaptive};
+use crate::block::{self, Marker};
+use crate::creative;
+use crate::error::{PcmError, Result};
+use crate::export::{Dither, FileFormat, SampleFormat};
+use crate::filter;
+use crate::g722;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "kebab-case")]
+pub enum Representation {
+    SignedMagnitude,
+    OnesComplement,
+    TwosComplement,
+    ExcessK,
+    Custom,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum Compression {
+    DPCM0,
+    DPCM1,
+    DPCM2,
+    DPCM3,
+    DPCMROQ,
+    DPCMSDX,
+    TableDPCM,
+    BRR,
+    PSXADPCM,
+    CreativeADPCM,
+    G722,
+    AdaptiveDPCM,
+    AppleIMA4,
+}
+
+impl Representation {
+    pub fn all() -> impl Iterator<Item = Representation> {
+        use Representation::*;
+        [
+            SignedMagnitude,
+            OnesComplement,
+            TwosComplement,
+            ExcessK,
+            Custom,
+        ]
+        .into_iter()
+    }
+
+    // How a byte becomes the signed residual d, for describe.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Representation::SignedMagnitude => {
+                "the sign bit (bit 7 unless --sign picks another) negates the \
+                 magnitude held in the remaining bits"
+            }
+            Representation::OnesComplement => {
+                "bytes from 128 up are the inverted bits negated, so 0xff is -0"
+            }
+            Representation::TwosComplement => "the byte read as an i8, 0x80 is -128",
+            Representation::ExcessK => {
+                "the byte minus k, which is 0 by default; k 128 (the excess128 \
+                 command) reads unsigned PCM"
+            }
+            Representation::Custom => {
+                "bytes below flip become flip - byte, then the byte is wrapped \
+                 or clamped to an i8 and k is subtracted"
+            }
+        }
+    }
+}
+
+impl Compression {
+    pub fn all() -> impl Iterator<Item = Compression> {
+        use Compression::*;
+        [
+            DPCM0,
+            DPCM1,
+            DPCM2,
+            DPCM3,
+            DPCMROQ,
+            DPCMSDX,
+            TableDPCM,
+            BRR,
+            PSXADPCM,
+            CreativeADPCM,
+            G722,
+            AdaptiveDPCM,
+            AppleIMA4,
+        ]
+        .into_iter()
+    }
+
+    // The formula, framing and typical sources, for describe. n1..n3 are the
+    // previous output samples and d the byte's residual.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Compression::DPCM0 => {
+                "plain PCM, no prediction: out = d << dpcm0-gain, 8 (x256) by \
+                 default, or d scaled to full scale with auto. One sample per \
+                 byte; raw 8-bit sound in most older games and sample packs"
+            }
+            Compression::DPCM1 => {
+                "first-order delta with a sign-magnitude byte: out = n1 + b for \
+                 b < 128, n1 - (b - 128) otherwise, ignoring the representation. \
+                 One sample per byte; simple delta-coded game audio"
+            }
+            Compression::DPCM2 => {
+                "second-order prediction: out = 2*n1 - n2 + d. One sample per \
+                 byte; residuals of a linear predictor"
+            }
+            Compression::DPCM3 => {
+                "third-order prediction: out = 3*n1 - 3*n2 + n3 + d. One sample \
+                 per byte; residuals of a quadratic predictor"
+            }
+            Compression::DPCMROQ => {
+                "squared delta from a sign-magnitude byte: out = n1 + (b*b >> \
+                 roq-shift) for b < 128, n1 - ((b - 128)^2 >> roq-shift) \
+                 otherwise. One sample per byte; id RoQ video audio"
+            }
+            Compression::DPCMSDX => {
+                "squared delta from a signed byte: out = n1 +/- 2*b*b, with \
+                 sdx-reset odd codes add to n1 and even ones start from 0. One \
+                 sample per byte; 3DO SDX2 audio"
+            }
+            Compression::TableDPCM => {
+                "delta from a lookup table: out = n1 + table[byte % len], with a \
+                 16-entry table each nibble is a sample. One or two samples per \
+                 byte; codecs with a delta table in the executable"
+            }
+            Compression::BRR => {
+                "SNES bit rate reduction: 9-byte blocks of a header (shift, \
+                 filter, loop and end flags) and 16 4-bit codes, each shifted \
+                 and added to one of four fixed predictors; Super Nintendo \
+                 sound"
+            }
+            Compression::PSXADPCM => {
+                "PlayStation SPU ADPCM: 16-byte blocks of a shift and filter \
+                 byte, a flags byte and 28 4-bit codes fed to one of five \
+                 predictors; PlayStation VAG and disc audio"
+            }
+            Compression::CreativeADPCM => {
+                "Sound Blaster ADPCM: a reference byte, then 4-, 3- or 2-bit \
+                 codes that pick a delta and adapt the step; Creative VOC files"
+            }
+            Compression::G722 => {
+                "ITU-T G.722 at 64 kbit/s: each byte holds a 6-bit low band and \
+                 2-bit high band ADPCM code and decodes to two 16 kHz samples; \
+                 wideband telephony and VoIP recordings"
+            }
+            Compression::AdaptiveDPCM => {
+                "adaptive delta without a step table: out = n1 + d * step, with \
+                 step growing on large codes and shrinking on small ones within \
+                 the adaptive limits. One sample per byte; unknown ADPCM codecs"
+            }
+            Compression::AppleIMA4 => {
+                "Apple IMA4: 34-byte blocks of a predictor and step index then \
+                 64 4-bit IMA ADPCM codes; QuickTime and AIFF-C ima4 audio"
+            }
+        }
+    }
+
+    // Whether the representation feeds d into this decoder.
+    pub fn uses_representation(self) -> bool {
+        !matches!(
+            self,
+            Compression::DPCM1
+                | Compression::DPCMROQ
+                | Compression::DPCMSDX
+                | Compression::TableDPCM
+                | Compression::BRR
+                | Compression::PSXADPCM
+                | Compression::AppleIMA4
+                | Compression::CreativeADPCM
+                | Compression::G722
+        )
+    }
+}
+
+// How Custom turns the folded byte into a signed 8-bit value: wrap
+// reinterprets bytes past 127 as negative, clamp saturates them and the
+// subtraction of k at the signed 8-bit limits.
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum Fold {
+    Wrap,
+    Clamp,
+}
+
+// The bit holding the sign of SignedMagnitude; the others, in order, are the
+// magnitude. Written 0 for the lowest bit and 1 for the highest, as before
+// any other position could be chosen, and bitN for the rest.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SignBit(pub u8);
+
+impl std::fmt::Display for SignBit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.0 {
+            0 => write!(f, "0"),
+            7 => write!(f, "1"),
+            bit => write!(f, "bit{}", bit),
+        }
+    }
+}
+
+impl std::str::FromStr for SignBit {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<SignBit> {
+        let bit = match s {
+            "0" => Some(0),
+            "1" => Some(7),
+            _ => s.strip_prefix("bit").and_then(|n| n.parse().ok()),
+        };
+        match bit {
+            Some(bit) if bit < 8 => Ok(SignBit(bit)),
+            _ => Err(PcmError::Invalid(format!(
+                "{:?}: expected 0 (lowest bit), 1 (highest bit) or bit0..bit7",
+                s
+            ))),
+        }
+    }
+}
+
+// How dpcm0 stretches d to 16 bits: a left shift, 8 (x256) by default, or
+// auto to scale the largest value the representation can produce to full
+// scale. Auto looks at the representation rather than the data so every
+// window of a file gets the same gain.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Gain {
+    Shift(u8),
+    Auto,
+}
+
+impl std::fmt::Display for Gain {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Gain::Shift(bits) => write!(f, "{}", bits),
+            Gain::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl std::str::FromStr for Gain {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<Gain> {
+        match s {
+            "auto" => Ok(Gain::Auto),
+            _ => match s.parse() {
+                Ok(bits) if bits < 16 => Ok(Gain::Shift(bits)),
+                _ => Err(PcmError::Invalid(format!(
+                    "{:?}: expected a shift of 0 to 15 or auto",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+// Which half of a byte nibble-based decoders read first. Native uses the
+// order the format itself defines.
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum NibbleOrder {
+    Native,
+    High,
+    Low,
+}
+
+impl NibbleOrder {
+    pub fn split(self, native: NibbleOrder, d8: u8) -> [u8; 2] {
+        let order = if self == NibbleOrder::Native {
+            native
+        } else {
+            self
+        };
+        match order {
+            NibbleOrder::Low => [d8 & 0xF, d8 >> 4],
+            _ => [d8 >> 4, d8 & 0xF],
+        }
+    }
+}
+
+// What block decoders do with a block whose header is out of range: decode
+// it with the values clamped, replace it with silence, or stop there.
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum OnError {
+    Skip,
+    Clamp,
+    Stop,
+}
+
+// What happens to a trailing block or byte-plane sample the input ends
+// partway through: dropped, or completed with zero bytes.
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum Partial {
+    Drop,
+    Pad,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opts {
+    pub from: usize,
+    pub to: usize,
+    pub step: usize,
+    pub skip: usize,
+    pub rate: u32,
+    pub zcr_threshold: f64,
+    pub windowed: bool,
+    pub warmup: Option<usize>,
+    pub max_samples: Option<usize>,
+    pub settle: bool,
+    pub highpass: f64,
+    pub bandpass: Option<(f64, f64)>,
+    pub gate: u16,
+    pub k: i16,
+    pub flip: u8,
+    pub mirror: u8,
+    pub fold: Fold,
+    pub sign: SignBit,
+    pub nibble_swap: bool,
+    pub mask: u8,
+    pub mask_shift: bool,
+    pub nibble_order: NibbleOrder,
+    pub creative_bits: u8,
+    pub sdx_reset: bool,
+    pub roq_shift: u8,
+    pub dpcm0_gain: Gain,
+    pub adaptive: Adaptive,
+    pub frame_size: Option<usize>,
+    pub byte_planes: bool,
+    pub plane_boundary: Option<usize>,
+    pub on_error: OnError,
+    pub partial: Partial,
+    pub representation: Representation,
+    pub compression: Compression,
+    pub stages: Vec<Compression>,
+    pub table: Vec<i16>,
+    pub stereo: bool,
+    pub planar: bool,
+    pub downmix: bool,
+    pub upmix: bool,
+    pub oversample: u32,
+    pub sample_format: SampleFormat,
+    pub file_format: FileFormat,
+    pub dither: Dither,
+    pub embed_markers: bool,
+    pub channel: usize,
+    pub right: Option<Box<Opts>>,
+}
+
+impl Opts {
+    // Where decode settings go: the right channel's own settings when those
+    // are split off and selected, otherwise these.
+    pub fn target(&mut self) -> &mut Opts {
+        if self.channel == 1 && self.right.is_some() {
+            self.right.as_deref_mut().unwrap()
+        } else {
+            self
+        }
+    }
+}
+
+impl Default for Opts {
+    fn default() -> Opts {
+        Opts {
+            from: 0,
+            to: 8192,
+            step: 1,
+            skip: 0,
+            rate: 16000,
+            zcr_threshold: 0.1,
+            windowed: false,
+            warmup: None,
+            max_samples: None,
+            settle: false,
+            highpass: 0.0,
+            bandpass: None,
+            gate: 0,
+            k: 0,
+            flip: 0,
+            mirror: 0,
+            fold: Fold::Wrap,
+            sign: SignBit(7),
+            nibble_swap: false,
+            mask: 0xFF,
+            mask_shift: false,
+            nibble_order: NibbleOrder::Native,
+            creative_bits: 4,
+            sdx_reset: true,
+            roq_shift: 0,
+            dpcm0_gain: Gain::Shift(8),
+            adaptive: Adaptive::default(),
+            frame_size: None,
+            byte_planes: false,
+            plane_boundary: None,
+            on_error: OnError::Clamp,
+            partial: Partial::Drop,
+            representation: Representation::TwosComplement,
+            compression: Compression::DPCM0,
+            stages: vec![],
+            table: vec![],
+            stereo: false,
+            planar: false,
+            downmix: false,
+            upmix: false,
+            oversample: 1,
+            sample_format: SampleFormat::I16,
+            file_format: FileFormat::Wav,
+            dither: Dither::None,
+            embed_markers: false,
+            channel: 0,
+            right: None,
+        }
+    }
+}
+
+// A delta table has either one entry per byte or one per nibble.
+pub fn load_table(path: &Path) -> Result<Vec<i16>> {
+    let text = fs::read_to_string(path)
+        .map_err(PcmError::io(format!("failed to read {}", path.display())))?;
+    let mut table = vec![];
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap();
+        for value in line.split(|c: char| c == ',' || c.is_whitespace()) {
+            if !value.is_empty() {
+                table.push(value.parse::<i16>().map_err(|e| {
+                    PcmError::Invalid(format!("{}: {:?}: {}", path.display(), value, e))
+                })?);
+            }
+        }
+    }
+    if table.len() != 16 && table.len() != 256 {
+        return Err(PcmError::UnsupportedFormat(format!(
+            "{}: expected 16 or 256 table entries, got {}",
+            path.display(),
+            table.len()
+        )));
+    }
+    Ok(table)
+}
+
+fn nibble_table(opt: &Opts) -> bool {
+    opt.compression == Compression::TableDPCM && opt.table.len() == 16
+}
+
+// Input bytes consumed and samples produced per decoding step. Every sample
+// of the byte-wise decoders comes from a single byte (or one nibble of it),
+// so step always strides over whole samples and never splits one. Block
+// formats and the ADPCM codecs ignore step.
+fn unit(opt: &Opts) -> (usize, usize) {
+    if opt.byte_planes {
+        (opt.step, 1)
+    } else if let Some(format) = block::block_format(opt) {
+        format
+    } else if opt.compression == Compression::CreativeADPCM {
+        (1, creative::samples_per_byte(opt.creative_bits))
+    } else if opt.compression == Compression::G722 {
+        (1, 2)
+    } else if nibble_table(opt) {
+        (opt.step, 2)
+    } else {
+        (opt.step, 1)
+    }
+}
+
+// Bytes at the start of the data that produce no samples.
+fn header_len(opt: &Opts) -> usize {
+    match opt.compression {
+        Compression::CreativeADPCM => 1,
+        _ => 0,
+    }
+}
+
+pub fn source_index(index: usize, opt: &Opts) -> usize {
+    let (bytes, samples) = unit(opt);
+    opt.skip + header_len(opt) + (index / samples) * bytes
+}
+
+// Bytes taken by the left channel of planar stereo. With an odd number of
+// decode units the left channel gets the extra one.
+fn planar_half(input_len: usize, opt: &Opts) -> usize {
+    let (bytes, _) = unit(opt);
+    (input_len.saturating_sub(opt.skip) / bytes).div_ceil(2) * bytes
+}
+
+// Like source_index, but follows the channel halves of planar stereo, which
+// depend on the input length.
+pub fn input_index(index: usize, input_len: usize, opt: &Opts) -> usize {
+    if opt.stereo && opt.planar {
+        let (bytes, samples) = unit(opt);
+        let base = opt.skip + (index % 2) * planar_half(input_len, opt);
+        base + header_len(opt) + (index / 2 / samples) * bytes
+    } else {
+        source_index(index, opt)
+    }
+}
+
+// Whole-byte transforms applied to the input before the representation.
+pub fn prepare(mut d8: u8, opt: &Opts) -> u8 {
+    if opt.nibble_swap {
+        d8 = d8.rotate_left(4);
+    }
+    d8 &= opt.mask;
+    if opt.mask_shift && opt.mask != 0 {
+        d8 >>= opt.mask.trailing_zeros();
+    }
+    d8
+}
+
+// Every decoder strides by step, so 0 would never move past the first byte.
+pub fn parse_step(s: &str) -> Result<usize> {
+    match s.parse() {
+        Ok(0) => Err(PcmError::Invalid("step must be at least 1".to_string())),
+        Ok(step) => Ok(step),
+        Err(e) => Err(PcmError::Invalid(format!("{:?}: {}", s, e))),
+    }
+}
+
+pub fn parse_hex(s: &str) -> Result<u8> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(digits, 16).map_err(|e| PcmError::Invalid(format!("{:?}: {}", s, e)))
+}
+
+pub fn represent(mut d8: u8, opt: &Opts) -> i16 {
+    match opt.representation {
+        Representation::Custom => {
+            let f = opt.flip;
+            let m = opt.mirror;
+            if d8 > m {
+                d8 = m + d8.overflowing_sub(m).0;
+            }
+            if d8 < f {
+                d8 = f.overflowing_sub(d8).0;
+            }
+            match opt.fold {
+                Fold::Wrap => ((d8 as i8) as i16).overflowing_sub(opt.k).0,
+                Fold::Clamp => {
+                    let d = d8.min(i8::MAX as u8) as i16;
+                    d.saturating_sub(opt.k)
+                        .clamp(i8::MIN as i16, i8::MAX as i16)
+                }
+            }
+        }
+        Representation::OnesComplement => {
+            if d8 < 128 {
+                d8 as i16
+            } else {
+                -(!d8 as i16)
+            }
+        }
+        Representation::TwosComplement => (d8 as i8) as i16,
+        Representation::SignedMagnitude => {
+            let bit = opt.sign.0;
+            let low = d8 & ((1u16 << bit) - 1) as u8;
+            let high = ((d8 as u16 >> (bit + 1)) << bit) as u8;
+            let magnitude = (high | low) as i16;
+            if (d8 >> bit) & 1 == 0 {
+                magnitude
+            } else {
+                -magnitude
+            }
+        }
+        Representation::ExcessK => (d8 as i16).overflowing_sub(opt.k).0,
+    }
+}
+
+pub fn decoded_len(input_len: usize, opt: &Opts) -> usize {
+    if opt.stereo {
+        // split as decode_stereo splits it, the shorter channel padded with
+        // silence to the longer one
+        let (left, right) = channel_lens(input_len, opt);
+        let frames =
+            decoded_len(left, &channel_opts(opt, 0)).max(decoded_len(right, &channel_opts(opt, 1)));
+        return frames * 2;
+    }
+    if opt.skip >= input_len {
+        0
+    } else if opt.byte_planes {
+        plane_samples(input_len - opt.skip, opt).div_ceil(opt.step)
+    } else {
+        let (bytes, samples) = unit(opt);
+        let len = (input_len - opt.skip).saturating_sub(header_len(opt));
+        if block::block_format(opt).is_some() && opt.partial == Partial::Drop {
+            len / bytes * samples
+        } else {
+            len.div_ceil(bytes) * samples
+        }
+    }
+}
+
+// Later stages see the previous stage's samples as the residual `d`, with
+// the low byte standing in for `d8`. DPCM0 only scales in the first stage.
+fn apply(opt: &Opts, compression: Compression, d8: u8, d: i16, out: &[i16], first: bool) -> i16 {
+    match compression {
+        Compression::DPCM0 => {
+            if first {
+                amplify(d, dpcm0_gain(opt))
+            } else {
+                d
+            }
+        }
+        Compression::DPCM1 => {
+            let err = d8;
+            let n1: i16 = if !out.is_empty() {
+                out[out.len() - 1]
+            } else {
+                0
+            };
+            if err < 128 {
+                n1.saturating_add(err as i16)
+            } else {
+                n1.saturating_sub((err - 128) as i16)
+            }
+        }
+        Compression::DPCM2 => {
+            let err = d;
+            let n1: i16 = if !out.is_empty() {
+                out[out.len() - 1]
+            } else {
+                0
+            };
+            let n2: i16 = if out.len() > 1 { out[out.len() - 2] } else { 0 };
+            n1.saturating_mul(2).saturating_sub(n2).saturating_add(err)
+        }
+        Compression::DPCM3 => {
+            let err = d;
+            let n1: i16 = if !out.is_empty() {
+                out[out.len() - 1]
+            } else {
+                0
+            };
+            let n2: i16 = if out.len() > 1 { out[out.len() - 2] } else { 0 };
+            let n3: i16 = if out.len() > 2 { out[out.len() - 3] } else { 0 };
+            n1.saturating_mul(3)
+                .saturating_sub(n2.saturating_mul(3))
+                .saturating_add(n3)
+                .saturating_add(err)
+        }
+        // roq_shift scales the square down for variants that grow slower
+        Compression::DPCMROQ => {
+            let err = d8;
+            let n1: i16 = if !out.is_empty() {
+                out[out.len() - 1]
+            } else {
+                0
+            };
+            let magnitude = (err & 0x7f) as i16;
+            let delta = (magnitude * magnitude)
+                .checked_shr(opt.roq_shift as u32)
+                .unwrap_or(0);
+            if err < 128 {
+                n1.saturating_add(delta)
+            } else {
+                n1.saturating_sub(delta)
+            }
+        }
+        // The code byte is signed and its square (doubled) is the delta,
+        // negative codes stepping down. With sdx_reset the low bit also picks
+        // the base: odd codes add to the previous sample, even codes start
+        // over from zero. Without it every code adds to the previous sample
+        // and the low bit is just part of the delta.
+        Compression::DPCMSDX => {
+            let n = d8 as i8 as i32;
+            let n1 = match out.last() {
+                Some(&n1) if !opt.sdx_reset || d8 & 1 != 0 => n1 as i32,
+                _ => 0,
+            };
+            let sq = n * n * 2;
+            let delta = if n < 0 { -sq } else { sq };
+            (n1 + delta).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        }
+        // block formats and the adpcm codecs only run as the first stage
+        Compression::BRR
+        | Compression::PSXADPCM
+        | Compression::AppleIMA4
+        | Compression::CreativeADPCM
+        | Compression::G722
+        | Compression::AdaptiveDPCM => d,
+        Compression::TableDPCM => {
+            let n1: i16 = if !out.is_empty() {
+                out[out.len() - 1]
+            } else {
+                0
+            };
+            if opt.table.is_empty() {
+                n1
+            } else {
+                n1.saturating_add(opt.table[d8 as usize % opt.table.len()])
+            }
+        }
+    }
+}
+
+// Byte-planar 16-bit: every low byte, then every high byte, with the high
+// plane starting `boundary` bytes into the data. An odd length puts the extra
+// byte in the low plane. Returns the boundary and the length of the high
+// plane.
+fn planes(data_len: usize, opt: &Opts) -> (usize, usize) {
+    let boundary = opt
+        .plane_boundary
+        .unwrap_or(data_len.div_ceil(2))
+        .min(data_len);
+    (boundary, data_len - boundary)
+}
+
+// Samples before step: the bytes of both planes, or with padding of the
+// longer plane.
+fn plane_samples(data_len: usize, opt: &Opts) -> usize {
+    let (low, high) = planes(data_len, opt);
+    match opt.partial {
+        Partial::Drop => low.min(high),
+        Partial::Pad => low.max(high),
+    }
+}
+
+// Reassembles two's complement samples from the planes; the representation
+// and compression do not apply to them, though stages and filters still do.
+fn decode_byte_planes(input: &[u8], opt: &Opts, out: &mut Vec<i16>) {
+    let data = input.get(opt.skip..).unwrap_or(&[]);
+    let (boundary, _) = planes(data.len(), opt);
+    let (low, high) = data.split_at(boundary);
+    let byte = |plane: &[u8], i: usize| plane.get(i).copied().unwrap_or(0);
+    out.extend(
+        (0..plane_samples(data.len(), opt))
+            .step_by(opt.step)
+            .map(|i| i16::from_le_bytes([byte(low, i), byte(high, i)])),
+    );
+}
+
+fn dpcm0_gain(opt: &Opts) -> i32 {
+    match opt.dpcm0_gain {
+        Gain::Shift(bits) => 1 << bits,
+        Gain::Auto => {
+            let peak = (0..=255u8)
+                .map(|b| (represent(prepare(b, opt), opt) as i32).abs())
+                .max()
+                .unwrap_or(0);
+            (i16::MAX as i32 / peak.max(1)).max(1)
+        }
+    }
+}
+
+fn amplify(d: i16, gain: i32) -> i16 {
+    (d as i32 * gain).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+// The representations only depend on the byte, so dpcm0 is a table lookup.
+fn decode_dpcm0(input: &[u8], opt: &Opts, out: &mut Vec<i16>) {
+    let mut lut = [0i16; 256];
+    let gain = dpcm0_gain(opt);
+    for (b, v) in lut.iter_mut().enumerate() {
+        *v = amplify(represent(prepare(b as u8, opt), opt), gain);
+    }
+    if opt.skip >= input.len() {
+        return;
+    }
+    let input = &input[opt.skip..];
+    if opt.step == 1 {
+        out.extend(input.iter().map(|&b| lut[b as usize]));
+    } else {
+        out.extend(input.iter().step_by(opt.step).map(|&b| lut[b as usize]));
+    }
+}
+
+fn decode_first(input: &[u8], opt: &Opts, out: &mut Vec<i16>) {
+    out.reserve(decoded_len(input.len(), opt));
+    let mut ix = opt.skip;
+    while ix < input.len() {
+        let d8 = prepare(input[ix], opt);
+        let d = represent(d8, opt);
+        if nibble_table(opt) {
+            for nibble in opt.nibble_order.split(NibbleOrder::High, d8) {
+                let sample = apply(opt, opt.compression, nibble, d, out, true);
+                out.push(sample);
+            }
+        } else {
+            let sample = apply(opt, opt.compression, d8, d, out, true);
+            out.push(sample);
+        }
+        let sample = out[out.len() - 1];
+        if ix < 10 {
+            log::debug!("d: {}, out: {}", d, sample);
+        }
+        ix += opt.step;
+    }
+}
+
+pub fn markers(input: &[u8], opt: &Opts) -> Vec<Marker> {
+    let mut markers = vec![];
+    block::decode_blocks(input, opt, &mut markers);
+    markers
+}
+
+// Byte offsets of the blocks with invalid headers.
+pub fn bad_blocks(input: &[u8], opt: &Opts) -> Vec<usize> {
+    block::bad_blocks(input, opt)
+}
+
+pub fn channels(opt: &Opts) -> usize {
+    if opt.stereo {
+        2
+    } else {
+        1
+    }
+}
+
+// One channel of stereo: the shared settings, with the right
+// channel's decode parameters swapped in when it has its own.
+fn channel_opts(opt: &Opts, channel: usize) -> Opts {
+    let mut o = Opts {
+        stereo: false,
+        planar: false,
+        skip: 0,
+        right: None,
+        ..opt.clone()
+    };
+    if let (1, Some(right)) = (channel, &opt.right) {
+        o.k = right.k;
+        o.flip = right.flip;
+        o.mirror = right.mirror;
+        o.fold = right.fold;
+        o.sign = right.sign;
+        o.nibble_swap = right.nibble_swap;
+        o.mask = right.mask;
+        o.mask_shift = right.mask_shift;
+        o.nibble_order = right.nibble_order;
+        o.creative_bits = right.creative_bits;
+        o.sdx_reset = right.sdx_reset;
+        o.roq_shift = right.roq_shift;
+        o.dpcm0_gain = right.dpcm0_gain;
+        o.adaptive = right.adaptive;
+        o.frame_size = right.frame_size;
+        o.on_error = right.on_error;
+        o.representation = right.representation;
+        o.compression = right.compression;
+        o.stages = right.stages.clone();
+        o.table = right.table.clone();
+    }
+    o
+}
+
+// Input bytes decode_stereo hands each channel. Interleaved units alternate
+// from the left, so an odd unit count or a short last unit leaves the left
+// channel longer.
+fn channel_lens(input_len: usize, opt: &Opts) -> (usize, usize) {
+    let (bytes, _) = unit(opt);
+    let data_len = input_len.saturating_sub(opt.skip);
+    if opt.planar {
+        let left = planar_half(input_len, opt).min(data_len);
+        return (left, data_len - left);
+    }
+    let units = data_len.div_ceil(bytes);
+    let (mut left, mut right) = (units.div_ceil(2) * bytes, units / 2 * bytes);
+    let short = units * bytes - data_len;
+    if units % 2 == 1 {
+        left -= short;
+    } else {
+        right -= short;
+    }
+    (left, right)
+}
+
+// Channels alternate every decode unit (a block for block formats) of the
+// left channel's settings, or with planar stereo fill one half of the data
+// each; the output is interleaved left, right.
+fn decode_stereo(input: &[u8], opt: &Opts) -> Vec<i16> {
+    let (bytes, _) = unit(opt);
+    let mut channels = [vec![], vec![]];
+    if opt.skip < input.len() {
+        let data = &input[opt.skip..];
+        if opt.planar {
+            let (left, right) = data.split_at(planar_half(input.len(), opt).min(data.len()));
+            channels = [left.to_vec(), right.to_vec()];
+        } else {
+            for (i, chunk) in data.chunks(bytes).enumerate() {
+                channels[i % 2].extend_from_slice(chunk);
+            }
+        }
+    }
+    let left = decode(&channels[0], &channel_opts(opt, 0));
+    let right = decode(&channels[1], &channel_opts(opt, 1));
+    (0..left.len().max(right.len()))
+        .flat_map(|i| {
+            [
+                left.get(i).copied().unwrap_or(0),
+                right.get(i).copied().unwrap_or(0),
+            ]
+        })
+        .collect()
+}
+
+pub fn decode(input: &[u8], opt: &Opts) -> Vec<i16> {
+    let mut out = vec![];
+    decode_into(input, opt, &mut out);
+    out
+}
+
+// Clears out and decodes into it, so callers decoding repeatedly (the REPL,
+// a parameter search) keep one allocation instead of a new Vec per call.
+// Predictors start from zero at the first byte, so a full decode is exact
+// from there on; decode_range instead primes them from `warmup` samples
+// before its window, and with `settle` both fade in over that same length
+// so the zero-primed transient is never heard.
+pub fn decode_into(input: &[u8], opt: &Opts, out: &mut Vec<i16>) {
+    out.clear();
+    if opt.stereo {
+        out.extend(decode_stereo(input, opt));
+        return;
+    }
+    if opt.byte_planes {
+        decode_byte_planes(input, opt, out);
+    } else if opt.compression == Compression::DPCM0 {
+        decode_dpcm0(input, opt, out);
+    } else if block::block_format(opt).is_some() {
+        out.extend(block::decode_blocks(input, opt, &mut vec![]));
+    } else if opt.compression == Compression::CreativeADPCM {
+        out.extend(creative::decode_creative(input, opt));
+    } else if opt.compression == Compression::G722 {
+        out.extend(g722::decode_g722(input, opt));
+    } else if opt.compression == Compression::AdaptiveDPCM {
+        out.extend(adaptive::decode_adaptive(input, opt));
+    } else {
+        decode_first(input, opt, out);
+    }
+    // each stage only looks back at its own earlier output, so it can
+    // overwrite the previous stage's samples in place
+    for &stage in &opt.stages {
+        for i in 0..out.len() {
+            let x = out[i];
+            out[i] = apply(opt, stage, x as u8, x, &out[..i], false);
+        }
+    }
+    filter::apply(out, opt);
+    if opt.settle {
+        fade_in(out, warmup(opt));
+    }
+}
+
+// Samples the byte-wise decoders look back at.
+const HISTORY: usize = 3;
+
+// The samples of `decode`, one at a time. The byte-wise decoders run lazily
+// with the last few samples of each stage as their history; block formats,
+// the ADPCM codecs, byte planes, stereo, filters and settle need the whole
+// output, so those decode everything on the first call and hand it out.
+struct DecodeIter<'a> {
+    input: &'a [u8],
+    opt: &'a Opts,
+    ix: usize,
+    history: Vec<VecDeque<i16>>,
+    pending: VecDeque<i16>,
+    eager: Option<std::vec::IntoIter<i16>>,
+    // dpcm0's gain, worked out once instead of for every sample
+    gain: i32,
+}
+
+// Whether decode_iter runs without decoding everything up front.
+pub fn decodes_lazily(opt: &Opts) -> bool {
+    !opt.stereo
+        && !opt.byte_planes
+        && !opt.settle
+        && !filter::active(opt)
+        && block::block_format(opt).is_none()
+        && !matches!(
+            opt.compression,
+            Compression::CreativeADPCM | Compression::G722 | Compression::AdaptiveDPCM
+        )
+}
+
+impl DecodeIter<'_> {
+    fn run(&mut self, d8: u8, d: i16) -> i16 {
+        let opt = self.opt;
+        let stages = std::iter::once((opt.compression, true))
+            .chain(opt.stages.iter().map(|&stage| (stage, false)));
+        let mut sample = d;
+        for ((compression, first), history) in stages.zip(&mut self.history) {
+            let (d8, d) = if first {
+                (d8, d)
+            } else {
+                (sample as u8, sample)
+            };
+            sample = if first && compression == Compression::DPCM0 {
+                amplify(d, self.gain)
+            } else {
+                apply(opt, compression, d8, d, history.make_contiguous(), first)
+            };
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(sample);
+        }
+        sample
+    }
+}
+
+impl Iterator for DecodeIter<'_> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if let Some(eager) = &mut self.eager {
+            return eager.next();
+        }
+        if self.pending.is_empty() && self.ix < self.input.len() {
+            let d8 = prepare(self.input[self.ix], self.opt);
+            let d = represent(d8, self.opt);
+            if nibble_table(self.opt) {
+                for nibble in self.opt.nibble_order.split(NibbleOrder::High, d8) {
+                    let sample = self.run(nibble, d);
+                    self.pending.push_back(sample);
+                }
+            } else {
+                let sample = self.run(d8, d);
+                self.pending.push_back(sample);
+            }
+            self.ix += self.opt.step;
+        }
+        self.pending.pop_front()
+    }
+}
+
+pub fn decode_iter<'a>(input: &'a [u8], opt: &'a Opts) -> impl Iterator<Item = i16> + 'a {
+    let eager = if decodes_lazily(opt) {
+        None
+    } else {
+        Some(decode(input, opt).into_iter())
+    };
+    DecodeIter {
+        input,
+        opt,
+        ix: opt.skip,
+        history: vec![VecDeque::with_capacity(HISTORY); 1 + opt.stages.len()],
+        pending: VecDeque::new(),
+        eager,
+        gain: dpcm0_gain(opt),
+    }
+}
+
+fn fade_in(out: &mut [i16], len: usize) {
+    for (i, x) in out.iter_mut().take(len).enumerate() {
+        *x = (*x as f64 * i as f64 / len as f64) as i16;
+    }
+}
+
+// Samples of context decoded ahead of a window so the predictor has settled.
+// dpcm0 and the representations are stateless and need none. dpcmsdx is
+// exact as soon as the context reaches back to a reset (even) byte; without
+// sdx_reset it integrates like dpcm1. The integrating variants (dpcm1/2/3,
+// dpcmroq, tabledpcm) carry their level indefinitely, so their context only
+// helps once saturation has pulled the decoder in line. brr and psxadpcm
+// only carry two samples of filter history from block to block. appleima4
+// restarts from each block's preamble, and a block of context carries the
+// low predictor bits the preamble leaves out. creativeadpcm restarts on the
+// byte before the window as its reference, and its clamped reference and
+// step settle within the context. g722 starts from a reset predictor and its
+// adaptive scale factors recover within the context. adaptivedpcm recovers
+// its step the same way, though its level carries like dpcm1.
+pub fn context(compression: Compression) -> usize {
+    match compression {
+        Compression::DPCM0 => 0,
+        Compression::DPCMSDX | Compression::AppleIMA4 => 64,
+        Compression::DPCM1 | Compression::DPCMROQ | Compression::TableDPCM => 1024,
+        Compression::DPCM2 | Compression::DPCM3 => 2048,
+        Compression::BRR | Compression::PSXADPCM => 256,
+        Compression::CreativeADPCM | Compression::G722 | Compression::AdaptiveDPCM => 1024,
+    }
+}
+
+// Without an explicit length, every stage contributes its own context.
+pub fn warmup(opt: &Opts) -> usize {
+    opt.warmup.unwrap_or_else(|| {
+        context(opt.compression) + opt.stages.iter().map(|&c| context(c)).sum::<usize>()
+    })
+}
+
+// Decodes samples `start..end` of what `decode` would return, starting
+// `warmup` samples early instead of from the beginning of the input.
+pub fn decode_range(input: &[u8], opt: &Opts, start: usize, end: usize, warmup: usize) -> Vec<i16> {
+    let (_, samples) = unit(opt);
+    let first = start.saturating_sub(warmup) / samples * samples;
+    let mut o = opt.clone();
+    // a header byte is taken from just before the window
+    o.skip = source_index(first, opt) - header_len(opt);
+    let limit = source_index(end.div_ceil(samples) * samples, opt).min(input.len());
+    let mut out = decode(&input[..limit], &o);
+    out.drain(..(start - first).min(out.len()));
+    out.truncate(end - start);
+    out
+}
+
+// Decodes only the `from..to` view, leaving the rest of the samples zero.
+// Byte planes are split relative to the whole input, so like stereo they are
+// always decoded whole.
+pub fn decode_window(input: &[u8], opt: &Opts) -> Vec<i16> {
+    if opt.stereo || opt.byte_planes {
+        return decode(input, opt);
+    }
+    let len = decoded_len(input.len(), opt);
+    let end = opt.to.min(len);
+    let start = opt.from.min(end);
+    let mut out = vec![0; len];
+    let samples = decode_range(input, opt, start, end, warmup(opt));
+    out[start..start + samples.len()].copy_from_slice(&samples);
+    out
+}
+
+// decode_window for files that decode to at most max_samples. Past that only
+// the plot range is kept, cut to max_samples, and the index of its first
+// sample is returned with it. Stereo is always decoded whole.
+pub fn decode_bounded(input: &[u8], opt: &Opts) -> (usize, Vec<i16>) {
+    let len = decoded_len(input.len(), opt);
+    match opt.max_samples {
+        Some(max) if !opt.stereo && !opt.byte_planes && len > max => {
+            let end = opt.to.min(len);
+            let start = opt.from.min(end);
+            let end = end.min(start + max);
+            (start, decode_range(input, opt, start, end, warmup(opt)))
+        }
+        _ => (0, decode_window(input, opt)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 + 11) as u8).collect()
+    }
+
+    fn table_file(name: &str, text: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pcm-extract-{}-{}.table", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(name: &str, text: &str) -> Result<Vec<i16>> {
+        let path = table_file(name, text);
+        let table = load_table(&path);
+        fs::remove_file(&path).unwrap();
+        table
+    }
+
+    #[test]
+    fn load_table_takes_16_or_256_entries() {
+        let nibbles = "# one entry per nibble\n0, 1, 2, 4,\n8 16 32 64\n-1,-2,-4,-8 # signed\n-16 -32 -64 -128\n";
+        assert_eq!(
+            load("nibbles", nibbles).unwrap(),
+            [0, 1, 2, 4, 8, 16, 32, 64, -1, -2, -4, -8, -16, -32, -64, -128]
+        );
+        let bytes: Vec<String> = (0..256).map(|i| (i - 128).to_string()).collect();
+        let table = load("bytes", &bytes.join("\n")).unwrap();
+        assert_eq!(table.len(), 256);
+        assert_eq!((table[0], table[255]), (-128, 127));
+    }
+
+    #[test]
+    fn load_table_rejects_other_lengths() {
+        for len in [0, 1, 15, 17, 255, 257] {
+            let text = vec!["1"; len].join(",");
+            match load(&format!("len{}", len), &text) {
+                Err(PcmError::UnsupportedFormat(what)) => {
+                    assert!(what.ends_with(&format!("got {}", len)), "{}", what)
+                }
+                other => panic!("{} entries: {:?}", len, other),
+            }
+        }
+        assert!(matches!(load("value", "1 2 x"), Err(PcmError::Invalid(_))));
+        assert!(matches!(
+            load("range", &vec!["40000"; 16].join(" ")),
+            Err(PcmError::Invalid(_))
+        ));
+    }
+
+    fn table_opt(table: Vec<i16>) -> Opts {
+        Opts {
+            compression: Compression::TableDPCM,
+            table,
+            ..Opts::default()
+        }
+    }
+
+    #[test]
+    fn nibble_table_steps_by_nibble() {
+        let opt = table_opt((0..16).map(|i| i * 10).collect());
+        // 0x12 0x34: deltas 10, 20, 30, 40, high nibble first
+        assert_eq!(decode(&[0x12, 0x34], &opt), [10, 30, 60, 100]);
+        let low = Opts {
+            nibble_order: NibbleOrder::Low,
+            ..opt.clone()
+        };
+        assert_eq!(decode(&[0x12, 0x34], &low), [20, 30, 70, 100]);
+        // step strides over whole bytes, both nibbles of each kept byte
+        let step = Opts { step: 2, ..opt };
+        assert_eq!(decode(&[0x12, 0x34, 0x56], &step), [10, 30, 80, 140]);
+        assert_eq!(decoded_len(3, &step), 4);
+    }
+
+    #[test]
+    fn byte_table_steps_by_byte() {
+        let opt = table_opt((0..256).map(|i| i as i16).collect());
+        assert_eq!(decode(&[0x12, 0x34], &opt), [0x12, 0x12 + 0x34]);
+        let step = Opts { step: 2, ..opt };
+        assert_eq!(decode(&[0x12, 0x34, 0x56], &step), [0x12, 0x12 + 0x56]);
+        assert_eq!(decoded_len(3, &step), 2);
+    }
+
+    #[test]
+    fn decoded_len_matches_decode() {
+        for compression in Compression::all() {
+            for (stereo, planar) in [(false, false), (true, false), (true, true)] {
+                for partial in [Partial::Drop, Partial::Pad] {
+                    for (step, skip) in [(1, 0), (2, 0), (1, 3)] {
+                        let opt = Opts {
+                            compression,
+                            stereo,
+                            planar,
+                            partial,
+                            step,
+                            skip,
+                            ..Opts::default()
+                        };
+                        for len in [0, 1, 2, 3, 9, 16, 17, 35, 68, 69, 100, 1097] {
+                            assert_eq!(
+                                decoded_len(len, &opt),
+                                decode(&input(len), &opt).len(),
+                                "{} of {} bytes, stereo {}, planar {}, {}, step {}, skip {}",
+                                compression,
+                                len,
+                                stereo,
+                                planar,
+                                partial,
+                                step,
+                                skip
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn step_must_be_at_least_1() {
+        assert_eq!(parse_step("3").unwrap(), 3);
+        for bad in ["0", "-1", "x"] {
+            assert!(
+                matches!(parse_step(bad), Err(PcmError::Invalid(_))),
+                "{}",
+                bad
+            );
+        }
+        let (mut opt, mut view) = (Opts::default(), crate::view::ViewOpts::default());
+        assert!(crate::state::from_text("step=0", &mut opt, &mut view).is_err());
+        assert_eq!(opt.step, 1);
+    }
+
+    #[test]
+    fn step_strides_whole_16_bit_samples() {
+        // low plane 01 02 03 04, high plane 10 20 30 40
+        let input = [0x01, 0x02, 0x03, 0x04, 0x10, 0x20, 0x30, 0x40];
+        let opt = Opts {
+            byte_planes: true,
+            step: 2,
+            ..Opts::default()
+        };
+        assert_eq!(decode(&input, &opt), [0x1001, 0x3003]);
+        assert_eq!(decoded_len(input.len(), &opt), 2);
+    }
+
+    fn signed_magnitude(sign: &str, d8: u8) -> i16 {
+        let opt = Opts {
+            representation: Representation::SignedMagnitude,
+            sign: sign.parse().unwrap(),
+            ..Opts::default()
+        };
+        represent(d8, &opt)
+    }
+
+    #[test]
+    fn sign_bit_keeps_0_and_1_as_lsb_and_msb() {
+        assert_eq!(signed_magnitude("0", 0x04), 2);
+        assert_eq!(signed_magnitude("0", 0x05), -2);
+        assert_eq!(signed_magnitude("0", 0xFF), -127);
+        assert_eq!(signed_magnitude("1", 0x05), 5);
+        assert_eq!(signed_magnitude("1", 0x85), -5);
+        assert_eq!(signed_magnitude("1", 0xFF), -127);
+        assert_eq!(SignBit(0).to_string(), "0");
+        assert_eq!(SignBit(7).to_string(), "1");
+    }
+
+    #[test]
+    fn sign_bit_in_the_middle_joins_the_bits_around_it() {
+        // bit 3 of 1010_1101 is set, and 1010 then 101 give 85
+        assert_eq!(signed_magnitude("bit3", 0b1010_1101), -85);
+        assert_eq!(signed_magnitude("bit3", 0b0010_0101), 21);
+        assert_eq!(signed_magnitude("bit3", 0b0000_1000), 0);
+        assert_eq!(SignBit(3).to_string(), "bit3");
+        assert_eq!("bit3".parse::<SignBit>().unwrap(), SignBit(3));
+        for bad in ["bit8", "2", "bit", "7"] {
+            assert!(
+                matches!(bad.parse::<SignBit>(), Err(PcmError::Invalid(_))),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    fn planes_opt(plane_boundary: Option<usize>, partial: Partial) -> Opts {
+        Opts {
+            byte_planes: true,
+            plane_boundary,
+            partial,
+            ..Opts::default()
+        }
+    }
+
+    #[test]
+    fn byte_planes_split_at_the_midpoint() {
+        // 0x1234, -2 and i16::MIN as low bytes then high bytes
+        let input = [0x34, 0xFE, 0x00, 0x12, 0xFF, 0x80];
+        let opt = planes_opt(None, Partial::Drop);
+        assert_eq!(decode(&input, &opt), [0x1234, -2, i16::MIN]);
+        // skipped bytes are not part of either plane
+        let skipped = Opts { skip: 2, ..opt };
+        assert_eq!(decode(&[0xAA, 0xBB, 0x34, 0x12], &skipped), [0x1234]);
+    }
+
+    #[test]
+    fn byte_planes_follow_the_boundary() {
+        // five bytes of low plane, the last three without a high byte
+        let input = [0x34, 0x78, 0x00, 0xAA, 0xBB, 0x12, 0x56];
+        let opt = planes_opt(Some(5), Partial::Drop);
+        assert_eq!(decode(&input, &opt), [0x1234, 0x5678]);
+        let padded = planes_opt(Some(5), Partial::Pad);
+        assert_eq!(decode(&input, &padded), [0x1234, 0x5678, 0x00, 0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn odd_byte_planes_put_the_extra_byte_low() {
+        let input = [0x34, 0x78, 0x9A, 0x12, 0x56];
+        assert_eq!(
+            decode(&input, &planes_opt(None, Partial::Drop)),
+            [0x1234, 0x5678]
+        );
+        assert_eq!(
+            decode(&input, &planes_opt(None, Partial::Pad)),
+            [0x1234, 0x5678, 0x9A]
+        );
+    }
+
+    // Mono settings for every decoder, with and without step and skip.
+    fn mono_opts() -> impl Iterator<Item = Opts> {
+        Compression::all().flat_map(|compression| {
+            [(1, 0), (2, 0), (3, 5)].map(move |(step, skip)| Opts {
+                compression,
+                step,
+                skip,
+                table: (0..16).map(|i| i * 100 - 800).collect(),
+                ..Opts::default()
+            })
+        })
+    }
+
+    #[test]
+    fn decode_iter_matches_decode() {
+        let data = input(1000);
+        for opt in mono_opts().chain([
+            Opts {
+                stages: vec![Compression::DPCM1],
+                ..Opts::default()
+            },
+            Opts {
+                byte_planes: true,
+                ..Opts::default()
+            },
+        ]) {
+            assert_eq!(
+                decode_iter(&data, &opt).collect::<Vec<_>>(),
+                decode(&data, &opt),
+                "{} step {} skip {}",
+                opt.compression,
+                opt.step,
+                opt.skip
+            );
+        }
+    }
+
+    #[test]
+    fn decode_range_matches_decode() {
+        let data = input(1000);
+        for opt in mono_opts() {
+            let all = decode(&data, &opt);
+            let len = all.len();
+            for (start, end) in [(0, len), (0, 1), (len / 3, len / 2), (len - 1, len)] {
+                // warmed up from the first sample the window is exact
+                assert_eq!(
+                    decode_range(&data, &opt, start, end, start),
+                    all[start..end],
+                    "{} step {} skip {}, {}..{}",
+                    opt.compression,
+                    opt.step,
+                    opt.skip,
+                    start,
+                    end
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decode_range_without_context_needs_no_warmup() {
+        let data = input(1000);
+        let dpcm0 = Opts::default();
+        let framed = Opts {
+            compression: Compression::BRR,
+            frame_size: Some(9),
+            ..Opts::default()
+        };
+        for opt in [dpcm0, framed] {
+            let all = decode(&data, &opt);
+            assert_eq!(decode_range(&data, &opt, 300, 700, 0), all[300..700]);
+            let window = Opts {
+                from: 300,
+                to: 700,
+                ..opt.clone()
+            };
+            let out = decode_window(&data, &window);
+            assert_eq!(out.len(), all.len());
+            assert_eq!(out[300..700], all[300..700]);
+            assert!(out[..300].iter().chain(&out[700..]).all(|&s| s == 0));
+        }
+    }
+
+    // Run with cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn dpcm0_lut_against_scalar() {
+        use std::hint::black_box;
+        use std::time::Instant;
+
+        let data = input(16 << 20);
+        for representation in [Representation::TwosComplement, Representation::Custom] {
+            let opt = Opts {
+                representation,
+                flip: 0x0F,
+                ..Opts::default()
+            };
+            let best = |f: &dyn Fn() -> Vec<i16>| {
+                (0..5)
+                    .map(|_| {
+                        let started = Instant::now();
+                        black_box(f());
+                        started.elapsed()
+                    })
+                    .min()
+                    .unwrap()
+            };
+            let lut = || {
+                let mut out = Vec::with_capacity(data.len());
+                decode_dpcm0(black_box(&data), &opt, &mut out);
+                out
+            };
+            let scalar = || {
+                let gain = dpcm0_gain(&opt);
+                black_box(&data)
+                    .iter()
+                    .map(|&b| amplify(represent(prepare(b, &opt), &opt), gain))
+                    .collect()
+            };
+            assert_eq!(lut(), scalar());
+            let (lut, scalar) = (best(&lut), best(&scalar));
+            let rate = |d: std::time::Duration| data.len() as f64 / d.as_secs_f64() / 1e6;
+            println!(
+                "{}: lut {:.1} ms ({:.0} M/s), scalar {:.1} ms ({:.0} M/s), {:.1}x",
+                representation,
+                lut.as_secs_f64() * 1000.0,
+                rate(lut),
+                scalar.as_secs_f64() * 1000.0,
+                rate(scalar),
+                scalar.as_secs_f64() / lut.as_secs_f64()
+            );
+        }
+    }
+}
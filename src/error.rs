@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// Errors from the decoding, input and export side of the library, so callers
+// can tell them apart without going through anyhow.
+#[derive(Debug)]
+pub enum PcmError {
+    EmptyInput(PathBuf),
+    RangeOutOfBounds {
+        path: PathBuf,
+        start: u64,
+        size: u64,
+    },
+    UnsupportedFormat(String),
+    Invalid(String),
+    Audio(String),
+    Io {
+        context: String,
+        source: io::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, PcmError>;
+
+impl PcmError {
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> PcmError {
+        let context = context.into();
+        move |source| PcmError::Io { context, source }
+    }
+}
+
+impl fmt::Display for PcmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PcmError::EmptyInput(path) => write!(f, "{} is empty", path.display()),
+            PcmError::RangeOutOfBounds { path, start, size } => write!(
+                f,
+                "range start {} is past the end of {} ({} bytes)",
+                start,
+                path.display(),
+                size
+            ),
+            PcmError::UnsupportedFormat(what) => write!(f, "unsupported format: {}", what),
+            PcmError::Invalid(what) => write!(f, "{}", what),
+            PcmError::Audio(what) => write!(f, "audio output: {}", what),
+            // {:#} adds the io error, as anyhow does for the whole chain
+            PcmError::Io { context, source } if f.alternate() => {
+                write!(f, "{}: {}", context, source)
+            }
+            PcmError::Io { context, .. } => write!(f, "{}", context),
+        }
+    }
+}
+
+// The io error is the source of an Io error rather than part of its message,
+// so it shows up once when the chain is printed, as with {:#} through anyhow.
+impl std::error::Error for PcmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PcmError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
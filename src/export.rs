@@ -0,0 +1,668 @@
+use crate::decode::{self, Opts};
+use crate::error::{PcmError, Result};
+use crate::flac;
+use std::fs;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+// What WAV exports store. f32 is normalized so i16::MIN maps to -1.0.
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum SampleFormat {
+    U8,
+    I16,
+    F32,
+}
+
+impl SampleFormat {
+    fn describe(self) -> &'static str {
+        match self {
+            SampleFormat::U8 => "8-bit",
+            SampleFormat::I16 => "16-bit",
+            SampleFormat::F32 => "32-bit float",
+        }
+    }
+}
+
+// Ogg Vorbis and Opus are named so asking for them explains why they are
+// missing: both need an encoder library this crate does not depend on.
+#[derive(parse_display::Display, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum FileFormat {
+    Wav,
+    Flac,
+}
+
+impl std::str::FromStr for FileFormat {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<FileFormat> {
+        match s {
+            "wav" => Ok(FileFormat::Wav),
+            "flac" => Ok(FileFormat::Flac),
+            "ogg" | "opus" => Err(PcmError::UnsupportedFormat(format!(
+                "{} exports need a Vorbis or Opus encoder, which is not built in; \
+                 export flac for a smaller lossless file",
+                s
+            ))),
+            _ => Err(PcmError::Invalid(format!("{:?}: expected wav or flac", s))),
+        }
+    }
+}
+
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum Dither {
+    None,
+    Tpdf,
+}
+
+// xorshift32 with a fixed seed, so dithered exports are still reproducible.
+struct Noise(u32);
+
+impl Noise {
+    fn next(&mut self) -> i32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 24) as i32
+    }
+
+    // Triangular between -255 and 255, one 8-bit step either way in 16-bit
+    // units.
+    fn tpdf(&mut self) -> i32 {
+        self.next() - self.next()
+    }
+}
+
+const SEED: u32 = 0x2545_f491;
+
+// Without dither the low byte is simply dropped.
+fn u8_sample(x: i16, dither: Dither, noise: &mut Noise) -> u8 {
+    let x = match dither {
+        Dither::None => x as i32,
+        Dither::Tpdf => x as i32 + 128 + noise.tpdf(),
+    };
+    ((x >> 8).clamp(i8::MIN as i32, i8::MAX as i32) + 128) as u8
+}
+
+fn to_u8(out: &[i16], dither: Dither) -> Vec<u8> {
+    let mut noise = Noise(SEED);
+    out.iter()
+        .map(|&x| u8_sample(x, dither, &mut noise))
+        .collect()
+}
+
+// Channels written on export, which can differ from what was decoded.
+pub fn channels(opt: &Opts) -> u16 {
+    if opt.stereo && !opt.downmix || !opt.stereo && opt.upmix {
+        2
+    } else {
+        1
+    }
+}
+
+fn downmix(out: &[i16]) -> Vec<i16> {
+    out.chunks(2)
+        .map(|f| {
+            let sum: i32 = f.iter().map(|&x| x as i32).sum();
+            (sum / f.len() as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16
+        })
+        .collect()
+}
+
+// Linear interpolation by an integer factor, per channel of interleaved
+// frames. The last frame is held rather than interpolated past the end.
+fn oversample(out: &[i16], channels: usize, factor: usize) -> Vec<i16> {
+    let frames = out.len() / channels;
+    let mut res = Vec::with_capacity(out.len() * factor);
+    for f in 0..frames {
+        let next = (f + 1).min(frames - 1);
+        for step in 0..factor {
+            for c in 0..channels {
+                let a = out[f * channels + c] as i32;
+                let b = out[next * channels + c] as i32;
+                res.push((a + (b - a) * step as i32 / factor as i32) as i16);
+            }
+        }
+    }
+    res
+}
+
+// A position (in frames of the decoded output) to name in the cue chunk. A
+// non-zero length makes it a region.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub position: usize,
+    pub label: String,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cues {
+    pub points: Vec<Cue>,
+    pub sustain_loop: Option<(usize, usize)>,
+}
+
+// Writes out as a WAV or FLAC file with the exported channel layout and
+// returns the summary line for it.
+pub fn export(path: &Path, out: &[i16], opt: &Opts) -> Result<String> {
+    export_marked(path, out, opt, &Cues::default())
+}
+
+// Like export, and with embed_markers also writes the cues into the WAV.
+pub fn export_marked(path: &Path, out: &[i16], opt: &Opts, cues: &Cues) -> Result<String> {
+    if opt.embed_markers && opt.file_format == FileFormat::Flac {
+        return Err(PcmError::UnsupportedFormat(
+            "markers can only be embedded in WAV exports".to_string(),
+        ));
+    }
+    let mixed;
+    let out = if opt.stereo && opt.downmix {
+        mixed = downmix(out);
+        &mixed
+    } else if !opt.stereo && opt.upmix {
+        mixed = out.iter().flat_map(|&x| [x, x]).collect();
+        &mixed
+    } else {
+        out
+    };
+    let channels = channels(opt);
+    let mut rate = opt.rate;
+    let upsampled;
+    let out = if opt.oversample > 1 {
+        upsampled = oversample(out, channels as usize, opt.oversample as usize);
+        rate *= opt.oversample;
+        &upsampled
+    } else {
+        out
+    };
+    match opt.file_format {
+        FileFormat::Wav => write_wav(path, out, rate, channels, opt.sample_format, opt.dither)?,
+        FileFormat::Flac => write_flac(path, out, rate, channels, opt.sample_format, opt.dither)?,
+    }
+    if opt.embed_markers {
+        embed(path, cues, opt, rate)?;
+    }
+    let bytes = fs::metadata(path)
+        .map_err(PcmError::io(format!("failed to stat {}", path.display())))?
+        .len();
+    Ok(summary(
+        path,
+        out.len(),
+        rate,
+        channels,
+        opt.sample_format,
+        bytes,
+    ))
+}
+
+// Whether export_streamed can write the export for opt.
+pub fn streams(opt: &Opts) -> bool {
+    opt.file_format == FileFormat::Wav && decode::decodes_lazily(opt)
+}
+
+// Like export_marked, but decodes input straight into the WAV file through a
+// buffered writer so the output never has to fit in memory. The header goes
+// out first with empty sizes that are filled in at the end.
+pub fn export_streamed(path: &Path, input: &[u8], opt: &Opts, cues: &Cues) -> Result<String> {
+    if !streams(opt) {
+        return Err(PcmError::UnsupportedFormat(
+            "only mono byte-wise decodes without filters stream into WAV exports".to_string(),
+        ));
+    }
+    let channels = channels(opt);
+    let factor = opt.oversample.max(1);
+    let rate = opt.rate * factor;
+    let file = fs::File::create(path)
+        .map_err(PcmError::io(format!("failed to create {}", path.display())))?;
+    let frames = stream_wav(file, input, opt, rate, channels)
+        .map_err(PcmError::io(format!("failed to write {}", path.display())))?;
+    if opt.embed_markers {
+        embed(path, cues, opt, rate)?;
+    }
+    let bytes = fs::metadata(path)
+        .map_err(PcmError::io(format!("failed to stat {}", path.display())))?
+        .len();
+    Ok(summary(
+        path,
+        frames * channels as usize,
+        rate,
+        channels,
+        opt.sample_format,
+        bytes,
+    ))
+}
+
+// Returns the frames written. Oversampling interpolates towards the next
+// sample and holds the last one, as in oversample.
+fn stream_wav(
+    file: fs::File,
+    input: &[u8],
+    opt: &Opts,
+    rate: u32,
+    channels: u16,
+) -> std::io::Result<usize> {
+    let (tag, bits) = match opt.sample_format {
+        SampleFormat::U8 => (wav::WAV_FORMAT_PCM, 8),
+        SampleFormat::I16 => (wav::WAV_FORMAT_PCM, 16),
+        SampleFormat::F32 => (wav::WAV_FORMAT_IEEE_FLOAT, 32),
+    };
+    let fmt: [u8; 16] = wav::Header::new(tag, channels, rate, bits).into();
+    let mut w = BufWriter::new(file);
+    w.write_all(b"RIFF\0\0\0\0WAVE")?;
+    w.write_all(&chunk(b"fmt ", &fmt))?;
+    w.write_all(b"data\0\0\0\0")?;
+    let mut noise = Noise(SEED);
+    let mut write = |w: &mut BufWriter<fs::File>, x: i16| -> std::io::Result<()> {
+        for _ in 0..channels {
+            match opt.sample_format {
+                SampleFormat::U8 => w.write_all(&[u8_sample(x, opt.dither, &mut noise)])?,
+                SampleFormat::I16 => w.write_all(&x.to_le_bytes())?,
+                SampleFormat::F32 => w.write_all(&(x as f32 / 32768.0).to_le_bytes())?,
+            }
+        }
+        Ok(())
+    };
+    let factor = opt.oversample.max(1) as i32;
+    let mut frames = 0;
+    let mut prev: Option<i16> = None;
+    for x in decode::decode_iter(input, opt) {
+        if let Some(a) = prev {
+            for step in 0..factor {
+                let (a, b) = (a as i32, x as i32);
+                write(&mut w, (a + (b - a) * step / factor) as i16)?;
+            }
+            frames += factor as usize;
+        }
+        prev = Some(x);
+    }
+    if let Some(a) = prev {
+        for _ in 0..factor {
+            write(&mut w, a)?;
+        }
+        frames += factor as usize;
+    }
+    let data = frames as u64 * channels as u64 * bits as u64 / 8;
+    if data % 2 == 1 {
+        w.write_all(&[0])?;
+    }
+    let riff = 4 + 24 + 8 + data + data % 2;
+    let too_large = || std::io::Error::other("the output is too large for a WAV file");
+    let riff = u32::try_from(riff).map_err(|_| too_large())?;
+    let mut file = w.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff.to_le_bytes())?;
+    file.seek(SeekFrom::Start(40))?;
+    file.write_all(&(data as u32).to_le_bytes())?;
+    Ok(frames)
+}
+
+// Appends the cue, label and loop chunks, with positions scaled by the
+// oversampling factor.
+fn embed(path: &Path, cues: &Cues, opt: &Opts, rate: u32) -> Result<()> {
+    let scaled = Cues {
+        points: cues
+            .points
+            .iter()
+            .map(|c| Cue {
+                position: c.position * opt.oversample as usize,
+                length: c.length * opt.oversample as usize,
+                ..c.clone()
+            })
+            .collect(),
+        sustain_loop: cues.sustain_loop.map(|(start, end)| {
+            (
+                start * opt.oversample as usize,
+                end * opt.oversample as usize,
+            )
+        }),
+    };
+    append_chunks(path, &marker_chunks(&scaled, rate))
+}
+
+// `samples` is the total across all channels; the line reports it per
+// channel.
+pub fn summary(
+    path: &Path,
+    samples: usize,
+    rate: u32,
+    channels: u16,
+    format: SampleFormat,
+    bytes: u64,
+) -> String {
+    let frames = samples / channels as usize;
+    format!(
+        "{}: {} samples, {:.3}s at {} Hz, {} channel(s), {}, {} bytes",
+        path.display(),
+        frames,
+        frames as f64 / rate as f64,
+        rate,
+        channels,
+        format.describe(),
+        bytes
+    )
+}
+
+// FLAC holds integer samples only, so f32 has no FLAC equivalent.
+pub fn write_flac(
+    path: &Path,
+    out: &[i16],
+    rate: u32,
+    channels: u16,
+    format: SampleFormat,
+    dither: Dither,
+) -> Result<()> {
+    match format {
+        SampleFormat::U8 => {
+            let out: Vec<i16> = to_u8(out, dither)
+                .into_iter()
+                .map(|b| b as i16 - 128)
+                .collect();
+            flac::write_flac(path, &out, rate, channels, 8)
+        }
+        SampleFormat::I16 => flac::write_flac(path, out, rate, channels, 16),
+        SampleFormat::F32 => Err(PcmError::UnsupportedFormat(
+            "FLAC cannot store f32 samples".to_string(),
+        )),
+    }
+}
+
+pub fn write_wav(
+    path: &Path,
+    out: &[i16],
+    rate: u32,
+    channels: u16,
+    format: SampleFormat,
+    dither: Dither,
+) -> Result<()> {
+    let mut out_file = fs::File::create(path)
+        .map_err(PcmError::io(format!("failed to create {}", path.display())))?;
+    let (h, data) = match format {
+        SampleFormat::U8 => (
+            wav::Header::new(wav::WAV_FORMAT_PCM, channels, rate, 8),
+            wav::BitDepth::Eight(to_u8(out, dither)),
+        ),
+        SampleFormat::I16 => (
+            wav::Header::new(wav::WAV_FORMAT_PCM, channels, rate, 16),
+            wav::BitDepth::Sixteen(out.to_vec()),
+        ),
+        SampleFormat::F32 => (
+            wav::Header::new(wav::WAV_FORMAT_IEEE_FLOAT, channels, rate, 32),
+            wav::BitDepth::ThirtyTwoFloat(out.iter().map(|&x| x as f32 / 32768.0).collect()),
+        ),
+    };
+    wav::write(h, &data, &mut out_file)
+        .map_err(PcmError::io(format!("failed to write {}", path.display())))
+}
+
+fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut c = id.to_vec();
+    c.extend((body.len() as u32).to_le_bytes());
+    c.extend(body);
+    if body.len() % 2 == 1 {
+        c.push(0);
+    }
+    c
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+// A cue chunk with one point per cue, its labels and region lengths in an
+// adtl list, and a smpl chunk holding the sustain loop. Positions are sample
+// frames of the data chunk; cue ids count from 1 in order and the loop
+// refers to none of them, which players ignore anyway.
+fn marker_chunks(cues: &Cues, rate: u32) -> Vec<u8> {
+    let mut chunks = vec![];
+    if !cues.points.is_empty() {
+        let mut cue = words(&[cues.points.len() as u32]);
+        let mut adtl = b"adtl".to_vec();
+        for (i, c) in cues.points.iter().enumerate() {
+            let id = i as u32 + 1;
+            let position = c.position as u32;
+            cue.extend(words(&[id, position]));
+            cue.extend(b"data");
+            cue.extend(words(&[0, 0, position]));
+            let mut labl = words(&[id]);
+            labl.extend(c.label.as_bytes());
+            labl.push(0);
+            adtl.extend(chunk(b"labl", &labl));
+            if c.length > 0 {
+                let mut ltxt = words(&[id, c.length as u32]);
+                ltxt.extend(b"rgn ");
+                ltxt.extend([0; 8]);
+                adtl.extend(chunk(b"ltxt", &ltxt));
+            }
+        }
+        chunks.extend(chunk(b"cue ", &cue));
+        chunks.extend(chunk(b"LIST", &adtl));
+    }
+    if let Some((start, end)) = cues.sustain_loop {
+        // unity note 60, no SMPTE offset, one forward loop with an inclusive
+        // end that plays forever
+        let period = (1_000_000_000 / rate.max(1) as u64) as u32;
+        let smpl = words(&[
+            0,
+            0,
+            period,
+            60,
+            0,
+            0,
+            0,
+            1,
+            0,
+            0,
+            0,
+            start as u32,
+            end.saturating_sub(1).max(start) as u32,
+            0,
+            0,
+        ]);
+        chunks.extend(chunk(b"smpl", &smpl));
+    }
+    chunks
+}
+
+// Adds chunks after the ones the wav crate wrote and fixes up the RIFF size.
+fn append_chunks(path: &Path, chunks: &[u8]) -> Result<()> {
+    let err = || PcmError::io(format!("failed to add markers to {}", path.display()));
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(err())?;
+    let len = file.seek(SeekFrom::End(0)).map_err(err())?;
+    // an odd-sized data chunk needs its pad byte before anything follows
+    let pad = len % 2;
+    // checked before writing, so a file past 4 GiB is left as it was
+    let riff = u32::try_from(len + pad + chunks.len() as u64 - 8).map_err(|_| {
+        err()(std::io::Error::other(
+            "the output is too large for a WAV file",
+        ))
+    })?;
+    if pad == 1 {
+        file.write_all(&[0]).map_err(err())?;
+    }
+    file.write_all(chunks).map_err(err())?;
+    file.seek(SeekFrom::Start(4)).map_err(err())?;
+    file.write_all(&riff.to_le_bytes()).map_err(err())
+}
+
+pub fn write_raw(
+    w: &mut dyn Write,
+    out: impl IntoIterator<Item = i16>,
+    endian: Endian,
+) -> Result<()> {
+    write_samples(BufWriter::new(w), out, endian).map_err(PcmError::io("failed to write samples"))
+}
+
+fn write_samples(
+    mut w: impl Write,
+    out: impl IntoIterator<Item = i16>,
+    endian: Endian,
+) -> std::io::Result<()> {
+    for x in out {
+        let bytes = match endian {
+            Endian::Little => x.to_le_bytes(),
+            Endian::Big => x.to_be_bytes(),
+        };
+        w.write_all(&bytes)?;
+    }
+    w.flush()
+}
+
+// `out` holds the decoded stream from sample `origin` on.
+pub fn write_csv(
+    path: &Path,
+    input: &[u8],
+    out: &[i16],
+    origin: usize,
+    opt: &Opts,
+) -> Result<usize> {
+    let file = fs::File::create(path)
+        .map_err(PcmError::io(format!("failed to create {}", path.display())))?;
+    write_rows(BufWriter::new(file), input, out, origin, opt)
+        .map_err(PcmError::io(format!("failed to write {}", path.display())))
+}
+
+fn write_rows(
+    mut file: impl Write,
+    input: &[u8],
+    out: &[i16],
+    origin: usize,
+    opt: &Opts,
+) -> std::io::Result<usize> {
+    writeln!(file, "index,input_byte,d,out")?;
+    let to = opt.to.min(origin + out.len());
+    let from = opt.from.max(origin).min(to);
+    let mut rows = 0;
+    for (index, o) in (from..to).zip(&out[from - origin..]) {
+        // the shorter planar channel is padded past the end of the input
+        let d8 = match input.get(decode::input_index(index, input.len(), opt)) {
+            Some(&d8) => d8,
+            None => continue,
+        };
+        let d = decode::represent(decode::prepare(d8, opt), opt);
+        writeln!(file, "{},{},{},{}", index, d8, d, o)?;
+        rows += 1;
+    }
+    file.flush()?;
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("pcm-extract-{}-{}.wav", std::process::id(), name))
+    }
+
+    #[test]
+    fn streamed_wav_matches_the_decoded_export() {
+        let input: Vec<u8> = (0..1001).map(|i| (i * 37 + 11) as u8).collect();
+        let cues = Cues {
+            points: vec![Cue {
+                position: 10,
+                label: "start".to_string(),
+                length: 0,
+            }],
+            sustain_loop: Some((100, 200)),
+        };
+        for sample_format in [SampleFormat::U8, SampleFormat::I16, SampleFormat::F32] {
+            for (oversample, upmix, embed_markers) in
+                [(1, false, false), (3, false, true), (1, true, true)]
+            {
+                let opt = Opts {
+                    compression: decode::Compression::DPCM1,
+                    sample_format,
+                    dither: Dither::Tpdf,
+                    oversample,
+                    upmix,
+                    embed_markers,
+                    ..Opts::default()
+                };
+                assert!(streams(&opt));
+                let (streamed, marked) = (temp("streamed"), temp("marked"));
+                // the summaries differ only in the path
+                let summary = export_streamed(&streamed, &input, &opt, &cues).unwrap();
+                let out = decode::decode(&input, &opt);
+                let expected = export_marked(&marked, &out, &opt, &cues).unwrap();
+                assert_eq!(
+                    summary.replace(&*streamed.to_string_lossy(), &marked.to_string_lossy()),
+                    expected
+                );
+                let (a, b) = (fs::read(&streamed).unwrap(), fs::read(&marked).unwrap());
+                fs::remove_file(&streamed).unwrap();
+                fs::remove_file(&marked).unwrap();
+                assert!(
+                    a == b,
+                    "{} oversample {} upmix {} markers {}",
+                    sample_format,
+                    oversample,
+                    upmix,
+                    embed_markers
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_wav_and_lazy_decodes_stream() {
+        assert!(streams(&Opts::default()));
+        let flac = Opts {
+            file_format: FileFormat::Flac,
+            ..Opts::default()
+        };
+        let stereo = Opts {
+            stereo: true,
+            ..Opts::default()
+        };
+        for opt in [flac, stereo] {
+            assert!(!streams(&opt));
+            let path = temp("refused");
+            assert!(matches!(
+                export_streamed(&path, &[0; 16], &opt, &Cues::default()),
+                Err(PcmError::UnsupportedFormat(_))
+            ));
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn ogg_and_opus_are_refused_by_name() {
+        assert_eq!("flac".parse::<FileFormat>().unwrap(), FileFormat::Flac);
+        for name in ["ogg", "opus"] {
+            assert!(matches!(
+                name.parse::<FileFormat>(),
+                Err(PcmError::UnsupportedFormat(_))
+            ));
+        }
+        assert!(matches!(
+            "mp3".parse::<FileFormat>(),
+            Err(PcmError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn markers_are_refused_past_4_gib() {
+        let path = temp("huge");
+        let file = fs::File::create(&path).unwrap();
+        // sparse, so nothing is actually written
+        file.set_len(1 << 32).unwrap();
+        let result = append_chunks(&path, &chunk(b"cue ", &[0; 4]));
+        let len = fs::metadata(&path).unwrap().len();
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(PcmError::Io { .. })));
+        assert_eq!(len, 1 << 32);
+    }
+}
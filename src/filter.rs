@@ -0,0 +1,116 @@
+use crate::decode::Opts;
+
+// Times for the gate envelope: it opens slightly ahead of a loud sample,
+// stays fully open through short dips such as zero crossings, then fades out.
+const ATTACK_MS: u32 = 2;
+const HOLD_MS: u32 = 20;
+const RELEASE_MS: u32 = 20;
+
+fn ms(opt: &Opts, ms: u32) -> usize {
+    (opt.rate as usize * ms as usize / 1000).max(1)
+}
+
+fn gate(out: &mut [i16], opt: &Opts) {
+    let threshold = opt.gate as i32;
+    let attack = ms(opt, ATTACK_MS);
+    let hold = ms(opt, HOLD_MS);
+    let release = ms(opt, RELEASE_MS);
+    let loud = |x: i16| (x as i32).abs() >= threshold;
+
+    let mut next = vec![usize::MAX; out.len()];
+    let mut n = usize::MAX;
+    for i in (0..out.len()).rev() {
+        if loud(out[i]) {
+            n = i;
+        }
+        next[i] = n;
+    }
+
+    let mut last = None;
+    for i in 0..out.len() {
+        if loud(out[i]) {
+            last = Some(i);
+            continue;
+        }
+        let after = match last {
+            Some(l) if i - l <= hold => 1.0,
+            Some(l) => 1.0 - (i - l - hold) as f64 / release as f64,
+            None => 0.0,
+        };
+        let before = match next[i] {
+            usize::MAX => 0.0,
+            n => 1.0 - (n - i) as f64 / attack as f64,
+        };
+        let gain = after.max(before).clamp(0.0, 1.0);
+        out[i] = (out[i] as f64 * gain).round() as i16;
+    }
+}
+
+// RBJ cookbook biquad, normalized so a0 is 1.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn highpass(rate: u32, cutoff: f64) -> Biquad {
+        let w = 2.0 * std::f64::consts::PI * cutoff.min(0.49 * rate as f64) / rate as f64;
+        let alpha = w.sin() / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+        let cos = w.cos();
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b: [
+                (1.0 + cos) / 2.0 / a0,
+                -(1.0 + cos) / a0,
+                (1.0 + cos) / 2.0 / a0,
+            ],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        }
+    }
+
+    // Unity gain at the geometric centre of low..high.
+    fn bandpass(rate: u32, low: f64, high: f64) -> Biquad {
+        let nyquist = 0.49 * rate as f64;
+        let (low, high) = (low.clamp(1.0, nyquist), high.clamp(1.0, nyquist));
+        let centre = (low * high).sqrt();
+        let q = centre / (high - low).max(1.0);
+        let w = 2.0 * std::f64::consts::PI * centre / rate as f64;
+        let alpha = w.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        Biquad {
+            b: [alpha / a0, 0.0, -alpha / a0],
+            a: [-2.0 * w.cos() / a0, (1.0 - alpha) / a0],
+        }
+    }
+
+    fn run(&self, out: &mut [i16]) {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        for s in out.iter_mut() {
+            let x = *s as f64;
+            let y =
+                self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+            x2 = x1;
+            x1 = x;
+            y2 = y1;
+            y1 = y;
+            *s = y.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        }
+    }
+}
+
+// Whether apply changes anything.
+pub fn active(opt: &Opts) -> bool {
+    opt.highpass > 0.0 || opt.bandpass.is_some() || opt.gate > 0
+}
+
+pub fn apply(out: &mut [i16], opt: &Opts) {
+    if opt.highpass > 0.0 {
+        Biquad::highpass(opt.rate, opt.highpass).run(out);
+    }
+    if let Some((low, high)) = opt.bandpass {
+        Biquad::bandpass(opt.rate, low, high).run(out);
+    }
+    if opt.gate > 0 {
+        gate(out, opt);
+    }
+}
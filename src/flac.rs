@@ -0,0 +1,344 @@
+use crate::error::{PcmError, Result};
+use std::fs;
+use std::path::Path;
+
+// A plain FLAC encoder: fixed-size frames of independently coded channels,
+// each subframe the cheapest of constant, verbatim or a fixed predictor of
+// order 0 to 4 with a single Rice partition. No MD5 signature is stored,
+// which the format allows.
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_ORDER: usize = 4;
+// Rice parameter 15 escapes to unencoded residuals.
+const MAX_RICE: u32 = 14;
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: vec![],
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    fn put(&mut self, value: u64, n: u32) {
+        for i in (0..n).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1);
+            self.bits += 1;
+            if self.bits == 8 {
+                self.bytes.push(self.acc as u8);
+                self.acc = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    fn put_signed(&mut self, value: i64, n: u32) {
+        self.put(value as u64 & ((1 << n) - 1), n);
+    }
+
+    fn zeros(&mut self, mut n: u64) {
+        while self.bits != 0 && n > 0 {
+            self.put(0, 1);
+            n -= 1;
+        }
+        while n >= 8 {
+            self.bytes.push(0);
+            n -= 8;
+        }
+        self.put(0, n as u32);
+    }
+
+    fn align(&mut self) {
+        if self.bits != 0 {
+            self.put(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in data {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Frame numbers use the UTF-8 style variable length code.
+fn put_utf8(w: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        w.put(n, 8);
+        return;
+    }
+    let mut len = 2;
+    while n >= 1 << (5 * len + 1) {
+        len += 1;
+    }
+    w.put(((0xFF << (8 - len)) & 0xFF) | (n >> (6 * (len - 1))), 8);
+    for i in (0..len - 1).rev() {
+        w.put(0x80 | ((n >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn residuals(x: &[i64], order: usize) -> Vec<i64> {
+    (order..x.len())
+        .map(|i| match order {
+            0 => x[i],
+            1 => x[i] - x[i - 1],
+            2 => x[i] - 2 * x[i - 1] + x[i - 2],
+            3 => x[i] - 3 * x[i - 1] + 3 * x[i - 2] - x[i - 3],
+            _ => x[i] - 4 * x[i - 1] + 6 * x[i - 2] - 4 * x[i - 3] + x[i - 4],
+        })
+        .collect()
+}
+
+fn zigzag(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+// The cheapest Rice parameter for the residuals and the bits it takes.
+fn rice(res: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE)
+        .map(|k| {
+            let bits = res.iter().map(|&r| (zigzag(r) >> k) + 1 + k as u64).sum();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+fn subframe(w: &mut BitWriter, x: &[i64], bps: u32) {
+    if x.iter().all(|&s| s == x[0]) {
+        w.put(0, 8);
+        w.put_signed(x[0], bps);
+        return;
+    }
+    let verbatim = x.len() as u64 * bps as u64;
+    let best = (0..=MAX_ORDER.min(x.len() - 1))
+        .map(|order| {
+            let res = residuals(x, order);
+            let (k, bits) = rice(&res);
+            // warm-up samples, coding method, partition order and parameter
+            (order, res, k, order as u64 * bps as u64 + 12 + bits)
+        })
+        .min_by_key(|&(_, _, _, bits)| bits)
+        .filter(|&(_, _, _, bits)| bits < verbatim);
+    match best {
+        Some((order, res, k, _)) => {
+            w.put(0b001000 | order as u64, 7);
+            w.put(0, 1);
+            for &s in &x[..order] {
+                w.put_signed(s, bps);
+            }
+            w.put(0, 2);
+            w.put(0, 4);
+            w.put(k as u64, 4);
+            for &r in &res {
+                let u = zigzag(r);
+                w.zeros(u >> k);
+                w.put(1, 1);
+                w.put(u & ((1 << k) - 1), k);
+            }
+        }
+        None => {
+            w.put(0b000001, 7);
+            w.put(0, 1);
+            for &s in x {
+                w.put_signed(s, bps);
+            }
+        }
+    }
+}
+
+fn frame(number: u64, block: &[i16], channels: usize, bps: u32) -> Vec<u8> {
+    let len = block.len() / channels;
+    let mut w = BitWriter::new();
+    w.put(0xFFF8, 16);
+    // block size from the header end, rate from STREAMINFO
+    w.put(0b0111, 4);
+    w.put(0, 4);
+    w.put(channels as u64 - 1, 4);
+    w.put(if bps == 8 { 0b001 } else { 0b100 }, 3);
+    w.put(0, 1);
+    put_utf8(&mut w, number);
+    w.put(len as u64 - 1, 16);
+    let crc = crc8(&w.bytes);
+    w.put(crc as u64, 8);
+    for c in 0..channels {
+        let x: Vec<i64> = block
+            .iter()
+            .skip(c)
+            .step_by(channels)
+            .map(|&s| s as i64)
+            .collect();
+        subframe(&mut w, &x, bps);
+    }
+    w.align();
+    let crc = crc16(&w.bytes);
+    w.put(crc as u64, 16);
+    w.bytes
+}
+
+// Writes interleaved samples of `bps` significant bits (8 or 16).
+pub fn write_flac(path: &Path, out: &[i16], rate: u32, channels: u16, bps: u32) -> Result<()> {
+    let channels = channels as usize;
+    let samples = out.len() / channels;
+    let frames: Vec<Vec<u8>> = out[..samples * channels]
+        .chunks(BLOCK_SIZE * channels)
+        .enumerate()
+        .map(|(i, block)| frame(i as u64, block, channels, bps))
+        .collect();
+    let block_size = samples.clamp(1, BLOCK_SIZE) as u64;
+    let frame_sizes = frames.iter().map(|f| f.len() as u64);
+
+    let mut w = BitWriter::new();
+    w.put(u32::from_be_bytes(*b"fLaC") as u64, 32);
+    // the only metadata block: STREAMINFO
+    w.put(1, 1);
+    w.put(0, 7);
+    w.put(34, 24);
+    w.put(block_size, 16);
+    w.put(block_size, 16);
+    w.put(frame_sizes.clone().min().unwrap_or(0), 24);
+    w.put(frame_sizes.max().unwrap_or(0), 24);
+    w.put(rate as u64, 20);
+    w.put(channels as u64 - 1, 3);
+    w.put(bps as u64 - 1, 5);
+    w.put(samples as u64, 36);
+    w.zeros(128);
+    let mut data = w.bytes;
+    for f in frames {
+        data.extend(f);
+    }
+    fs::write(path, data).map_err(PcmError::io(format!("failed to write {}", path.display())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crcs_match_their_check_values() {
+        // the CRC catalogue check input; CRC-8 poly 0x07 and CRC-16 poly
+        // 0x8005, both from zero and unreflected
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+        assert_eq!(crc8(&[]), 0);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn frame_numbers_use_utf8_lengths() {
+        let utf8 = |n| {
+            let mut w = BitWriter::new();
+            put_utf8(&mut w, n);
+            w.bytes
+        };
+        assert_eq!(utf8(0), [0x00]);
+        assert_eq!(utf8(0x7F), [0x7F]);
+        assert_eq!(utf8(0x80), [0xC2, 0x80]);
+        assert_eq!(utf8(0x7FF), [0xDF, 0xBF]);
+        assert_eq!(utf8(0x800), [0xE0, 0xA0, 0x80]);
+        assert_eq!(utf8(0x10000), [0xF0, 0x90, 0x80, 0x80]);
+    }
+
+    // A frame ends in the CRC-16 of everything before it, and its header
+    // in the CRC-8 of the header.
+    fn check_crcs(f: &[u8]) {
+        assert_eq!(f[7], crc8(&f[..7]));
+        let (body, crc) = f.split_at(f.len() - 2);
+        assert_eq!(crc, crc16(body).to_be_bytes());
+    }
+
+    #[test]
+    fn constant_frame() {
+        let f = frame(0, &[5, 5, 5, 5], 1, 16);
+        // sync, block size from the end of the header, mono 16-bit, frame 0,
+        // 4 samples
+        assert_eq!(f[..7], [0xFF, 0xF8, 0x70, 0x08, 0x00, 0x00, 0x03]);
+        // a constant subframe holding 5
+        assert_eq!(f[8..f.len() - 2], [0x00, 0x00, 0x05]);
+        check_crcs(&f);
+    }
+
+    #[test]
+    fn fixed_predictor_frame() {
+        let ramp: Vec<i16> = (1000..1008).collect();
+        let f = frame(0, &ramp, 1, 16);
+        assert_eq!(f[..7], [0xFF, 0xF8, 0x70, 0x08, 0x00, 0x00, 0x07]);
+        // a ramp costs least as a first-order predictor: warm-up sample 1000,
+        // Rice parameter 0, seven residuals of 1 (zigzag 2) coded in unary as
+        // 001, then padding
+        assert_eq!(
+            f[8..f.len() - 2],
+            [0x12, 0x03, 0xE8, 0x00, 0x09, 0x24, 0x92]
+        );
+        check_crcs(&f);
+    }
+
+    #[test]
+    fn verbatim_frame_of_noise() {
+        let block = [0x1234, -0x7000, 0x0FF0, 0x7FFF];
+        let f = frame(0, &block, 1, 16);
+        let mut verbatim = vec![0x02];
+        verbatim.extend(block.iter().flat_map(|s| s.to_be_bytes()));
+        assert_eq!(f[8..f.len() - 2], verbatim[..]);
+        check_crcs(&f);
+    }
+
+    #[test]
+    fn stream_starts_with_streaminfo() {
+        let path = std::env::temp_dir().join(format!("pcm-extract-{}.flac", std::process::id()));
+        let out = [0, 1, 2, 3, 4, 5, 6, 7, 5, 5, 5, 5];
+        write_flac(&path, &out, 16000, 2, 16).unwrap();
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(data[..4], *b"fLaC");
+        // last metadata block, STREAMINFO, 34 bytes
+        assert_eq!(data[4..8], [0x80, 0x00, 0x00, 0x22]);
+        // six frames per channel as the minimum and maximum block size
+        assert_eq!(data[8..12], [0x00, 0x06, 0x00, 0x06]);
+        let frame = frame(0, &out, 2, 16);
+        let size = (frame.len() as u32).to_be_bytes();
+        assert_eq!(
+            data[12..18],
+            [size[1], size[2], size[3], size[1], size[2], size[3]]
+        );
+        // 16000 Hz in 20 bits, 2 channels, 16 bits, 6 samples in 36 bits
+        assert_eq!(
+            data[18..26],
+            [0x03, 0xE8, 0x02, 0xF0, 0x00, 0x00, 0x00, 0x06]
+        );
+        // no MD5
+        assert_eq!(data[26..42], [0; 16]);
+        assert_eq!(data[42..], frame[..]);
+    }
+}
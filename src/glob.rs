@@ -0,0 +1,157 @@
+use crate::error::{PcmError, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Expands --input patterns the way the glob crate would for the cases that
+// matter here: *, ? and [...] classes within path components. It is written
+// out here because the glob crate cannot be fetched in the offline build.
+
+pub fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|i| matches(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && matches(&pattern[1..], &name[1..]),
+        // a ']' first in the set is one of its characters, so [*], [?], [[]
+        // and []] match those literally
+        Some('[') => {
+            let start = match pattern.get(1) {
+                Some('!' | '^') => 2,
+                _ => 1,
+            };
+            let close = match pattern.iter().skip(start + 1).position(|&c| c == ']') {
+                Some(i) => start + 1 + i,
+                None => return name.first() == Some(&'[') && matches(&pattern[1..], &name[1..]),
+            };
+            let (negate, set) = (start == 2, &pattern[start..close]);
+            let c = match name.first() {
+                Some(&c) => c,
+                None => return false,
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negate && matches(&pattern[close + 1..], &name[1..])
+        }
+        Some(&p) => name.first() == Some(&p) && matches(&pattern[1..], &name[1..]),
+    }
+}
+
+pub fn expand(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let part = match component {
+            Component::Normal(part) => part.to_string_lossy(),
+            other => {
+                paths.iter_mut().for_each(|p| p.push(other.as_os_str()));
+                continue;
+            }
+        };
+        if !is_pattern(&part) {
+            paths.iter_mut().for_each(|p| p.push(&*part));
+            continue;
+        }
+        let chars: Vec<char> = part.chars().collect();
+        let mut next = vec![];
+        for dir in &paths {
+            let read_from = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            let entries = match fs::read_dir(read_from) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') && !part.starts_with('.') {
+                    continue;
+                }
+                let name_chars: Vec<char> = name.chars().collect();
+                if matches(&chars, &name_chars) {
+                    next.push(dir.join(&name));
+                }
+            }
+        }
+        paths = next;
+    }
+    let mut paths: Vec<PathBuf> = paths.into_iter().filter(|p| p.is_file()).collect();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(PcmError::Invalid(format!("no files match {}", pattern)));
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, name: &str) -> bool {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+        matches(&chars(pattern), &chars(name))
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob("*.raw", "a.raw"));
+        assert!(glob("*.raw", ".raw"));
+        assert!(glob("a*b*c", "abc"));
+        assert!(glob("a*b*c", "axxbyyc"));
+        assert!(!glob("*.raw", "a.wav"));
+        assert!(!glob("a*c", "abcd"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob("track?.bin", "track1.bin"));
+        assert!(!glob("track?.bin", "track.bin"));
+        assert!(!glob("track?.bin", "track12.bin"));
+    }
+
+    #[test]
+    fn classes_match_sets_and_ranges() {
+        assert!(glob("[abc].raw", "b.raw"));
+        assert!(!glob("[abc].raw", "d.raw"));
+        assert!(glob("t[0-9][0-9]", "t42"));
+        assert!(!glob("t[0-9]", "tx"));
+        assert!(glob("[!0-9]x", "ax"));
+        assert!(glob("[^0-9]x", "ax"));
+        assert!(!glob("[!0-9]x", "5x"));
+        assert!(glob("[a-]", "-"));
+        assert!(!glob("[abc]", ""));
+    }
+
+    #[test]
+    fn brackets_escape_special_characters() {
+        assert!(glob("a[*]", "a*"));
+        assert!(!glob("a[*]", "ab"));
+        assert!(glob("a[?]", "a?"));
+        assert!(!glob("a[?]", "ab"));
+        assert!(glob("[[]x", "[x"));
+        assert!(glob("[]]x", "]x"));
+        assert!(glob("[!]]x", "ax"));
+        assert!(!glob("[!]]x", "]x"));
+        // an unclosed bracket is a plain character
+        assert!(glob("a[b", "a[b"));
+        assert!(glob("[]", "[]"));
+    }
+
+    #[test]
+    fn no_match_is_an_error() {
+        let err = expand("/nonexistent-pcm-extract-dir/*.raw").unwrap_err();
+        assert!(matches!(err, PcmError::Invalid(_)), "{:?}", err);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::error::{PcmError, Result};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Deref;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+// A read-only mapping of a whole input file, for --mmap. Only unix systems
+// map the file; elsewhere load reads it like any other input. This is the
+// small part of memmap2 the decode needs, written against libc directly
+// because memmap2 cannot be fetched in the offline build.
+#[cfg(unix)]
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mmap {
+    pub fn open(path: &Path) -> Result<Mmap> {
+        let file = fs::File::open(path)
+            .map_err(PcmError::io(format!("failed to open {}", path.display())))?;
+        let len = file
+            .metadata()
+            .map_err(PcmError::io(format!("failed to stat {}", path.display())))?
+            .len() as usize;
+        if len == 0 {
+            return Err(PcmError::EmptyInput(path.to_path_buf()));
+        }
+        // SAFETY: a private read-only mapping of len bytes of a file we just
+        // opened; the mapping stays valid after the descriptor is closed and
+        // nothing here ever writes through it. It is only sound as long as no
+        // other process changes the file while it is mapped, which --mmap
+        // asks of the user: writes by others may show through the private
+        // mapping, breaking the promise that the &[u8] it derefs to never
+        // changes, and a file truncated under the mapping raises SIGBUS on
+        // the first read of a page past its new end. That kills the process
+        // rather than reading freed memory, which is why the mapping is
+        // opt-in and the default reads the file into an owned buffer.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(PcmError::io(format!("failed to map {}", path.display()))(
+                std::io::Error::last_os_error(),
+            ));
+        }
+        Ok(Mmap { ptr, len })
+    }
+}
+
+#[cfg(unix)]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr points to len mapped bytes until drop unmaps them, so
+        // the slice never outlives the mapping. The pages stay readable while
+        // the file keeps its length; if another process truncates it, reading
+        // a page past the new end raises SIGBUS and ends the process instead
+        // of handing out memory that is not ours, so the slice is never
+        // backed by anything but this file. See open for the rest of the
+        // contract --mmap asks of the user.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len come from a successful mmap call.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+pub enum Input {
+    Owned(Vec<u8>),
+    #[cfg(unix)]
+    Mapped(Mmap),
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Input::Owned(data) => data,
+            #[cfg(unix)]
+            Input::Mapped(map) => map,
+        }
+    }
+}
+
+pub fn load(paths: &[PathBuf], mmap: bool) -> Result<Input> {
+    if mmap {
+        if paths.len() != 1 {
+            return Err(PcmError::Invalid(format!(
+                "--mmap needs a single input file, got {}",
+                paths.len()
+            )));
+        }
+        #[cfg(unix)]
+        return Ok(Input::Mapped(Mmap::open(&paths[0])?));
+    }
+    let mut input = vec![];
+    for filename in paths {
+        if paths.len() > 1 {
+            eprintln!("{}: starts at byte {}", filename.display(), input.len());
+        }
+        let mut file = fs::File::open(filename).map_err(PcmError::io(format!(
+            "failed to open {}",
+            filename.display()
+        )))?;
+        file.read_to_end(&mut input).map_err(PcmError::io(format!(
+            "failed to read {}",
+            filename.display()
+        )))?;
+    }
+    Ok(Input::Owned(input))
+}
+
+pub fn load_range(paths: &[PathBuf], start: u64, len: u64) -> Result<Input> {
+    if paths.len() != 1 {
+        return Err(PcmError::Invalid(format!(
+            "--range-bytes needs a single input file, got {}",
+            paths.len()
+        )));
+    }
+    let path = &paths[0];
+    let read_err = || PcmError::io(format!("failed to read {}", path.display()));
+    let mut file =
+        fs::File::open(path).map_err(PcmError::io(format!("failed to open {}", path.display())))?;
+    let size = file.metadata().map_err(read_err())?.len();
+    if start >= size {
+        return Err(PcmError::RangeOutOfBounds {
+            path: path.clone(),
+            start,
+            size,
+        });
+    }
+    file.seek(SeekFrom::Start(start)).map_err(read_err())?;
+    let mut input = Vec::with_capacity(len.min(size - start) as usize);
+    file.take(len).read_to_end(&mut input).map_err(read_err())?;
+    Ok(Input::Owned(input))
+}
+
+fn le16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+// The fmt chunk of a RIFF WAVE file, searched for past any chunks before it.
+fn wave_format(data: &[u8]) -> Option<String> {
+    let mut at = 12;
+    loop {
+        let size = le32(data, at + 4)? as usize;
+        if data.get(at..at + 4)? == b"fmt " {
+            let tag = match le16(data, at + 8)? {
+                1 => "PCM".to_string(),
+                2 => "MS ADPCM".to_string(),
+                3 => "IEEE float".to_string(),
+                6 => "A-law".to_string(),
+                7 => "mu-law".to_string(),
+                0x11 => "IMA ADPCM".to_string(),
+                0xFFFE => "extensible".to_string(),
+                tag => format!("format 0x{:04x}", tag),
+            };
+            return Some(format!(
+                "RIFF WAVE, {}, {} channel(s), {} Hz, {}-bit",
+                tag,
+                le16(data, at + 10)?,
+                le32(data, at + 12)?,
+                le16(data, at + 22)?
+            ));
+        }
+        // chunks are padded to an even length
+        at += 8 + size + size % 2;
+    }
+}
+
+// Names a known container or codec header at the start of the data.
+pub fn sniff(data: &[u8]) -> Option<String> {
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        return Some(wave_format(data).unwrap_or_else(|| "RIFF WAVE".to_string()));
+    }
+    let magics: [(&[u8], &str); 8] = [
+        (b"FORM", "IFF/AIFF"),
+        (b"OggS", "Ogg"),
+        (b"fLaC", "FLAC"),
+        (b"VAGp", "PlayStation VAG"),
+        (b"Creative Voice File", "Creative VOC"),
+        (b".snd", "Sun/NeXT au"),
+        (b"ID3", "MP3 with ID3 tag"),
+        (b"#!AMR", "AMR"),
+    ];
+    magics
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, name)| name.to_string())
+}
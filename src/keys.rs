@@ -0,0 +1,132 @@
+use crate::error::{PcmError, Result};
+use std::io;
+
+// Single key presses from a terminal put into non-canonical mode, for
+// scrubbing without pressing Enter. The previous terminal settings come back
+// when RawMode is dropped.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Key {
+    Left,
+    Right,
+    Up,
+    Down,
+    Escape,
+    Char(u8),
+}
+
+#[cfg(unix)]
+pub struct RawMode {
+    saved: libc::termios,
+}
+
+// Other systems have no termios to switch, so scrubbing is unix only.
+#[cfg(not(unix))]
+pub struct RawMode;
+
+#[cfg(not(unix))]
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        Err(PcmError::Invalid(
+            "raw-key mode needs a unix terminal".to_string(),
+        ))
+    }
+
+    pub fn read(&self) -> io::Result<Key> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(unix)]
+impl RawMode {
+    pub fn enable() -> Result<RawMode> {
+        // SAFETY: isatty only inspects the descriptor.
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return Err(PcmError::Invalid(
+                "raw-key mode needs a terminal on stdin".to_string(),
+            ));
+        }
+        // SAFETY: termios is plain data that tcgetattr fills in.
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        // SAFETY: a valid descriptor and a pointer to a termios.
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(PcmError::io("failed to read the terminal settings")(
+                io::Error::last_os_error(),
+            ));
+        }
+        let mut raw = saved;
+        // Ctrl-C arrives as a key instead of killing the process with the
+        // terminal left in this mode
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        // SAFETY: as above.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(PcmError::io("failed to switch the terminal mode")(
+                io::Error::last_os_error(),
+            ));
+        }
+        Ok(RawMode { saved })
+    }
+
+    pub fn read(&self) -> io::Result<Key> {
+        let key = match read_byte()? {
+            0x1b => match read_pending()? {
+                Some(b'[') => match read_pending()? {
+                    Some(b'A') => Key::Up,
+                    Some(b'B') => Key::Down,
+                    Some(b'C') => Key::Right,
+                    Some(b'D') => Key::Left,
+                    _ => Key::Escape,
+                },
+                _ => Key::Escape,
+            },
+            b => Key::Char(b),
+        };
+        Ok(key)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restores the settings read in enable.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_byte() -> io::Result<u8> {
+    let mut b = 0u8;
+    // SAFETY: reads at most one byte into b.
+    match unsafe {
+        libc::read(
+            libc::STDIN_FILENO,
+            &mut b as *mut u8 as *mut libc::c_void,
+            1,
+        )
+    } {
+        1 => Ok(b),
+        0 => Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// The rest of an escape sequence arrives right behind the escape byte; a
+// lone Escape key has nothing following it.
+#[cfg(unix)]
+fn read_pending() -> io::Result<Option<u8>> {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: one valid pollfd.
+    match unsafe { libc::poll(&mut fds, 1, 50) } {
+        1 => read_byte().map(Some),
+        0 => Ok(None),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
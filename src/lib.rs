@@ -0,0 +1,24 @@
+// -*- coding: utf-8 -*-
+
+pub mod adaptive;
+pub mod analysis;
+pub mod batch;
+pub mod block;
+pub mod cache;
+pub mod creative;
+pub mod decode;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod flac;
+pub mod g722;
+pub mod glob;
+pub mod input;
+pub mod keys;
+pub mod playback;
+pub mod plot;
+pub mod report;
+pub mod state;
+pub mod toc;
+pub mod units;
+pub mod view;
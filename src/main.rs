@@ -1,76 +1,563 @@
 // -*- coding: utf-8 -*-
 
 use anyhow::{self};
-use clap::{App, Arg};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use easy_repl::{repl::LoopStatus, validator, CommandStatus, Repl};
+use clap::{App, Arg, ArgMatches, Shell};
+use easy_repl::{validator, CommandStatus};
+use rayon::prelude::*;
 use std::cell::RefCell;
 use std::fs;
-use std::io::Read;
-use std::path::Path;
-use std::sync::{Arc, Barrier};
-use textplots::{Chart, Plot, Shape};
-
-#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone)]
-#[display(style = "kebab-case")]
-enum Representation {
-    SignedMagnitude,
-    OnesComplement,
-    TwosComplement,
-    ExcessK,
-    Custom,
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use pcm_extract::analysis::{self, Depth, WindowFunction};
+use pcm_extract::block::MarkerKind;
+use pcm_extract::decode::{
+    self, decode, Compression, Fold, Gain, NibbleOrder, OnError, Opts, Partial, Representation,
+    SignBit,
+};
+use pcm_extract::error::PcmError;
+use pcm_extract::keys::{Key, RawMode};
+use pcm_extract::toc::{self, TocFormat};
+use pcm_extract::view::ViewOpts;
+use pcm_extract::{batch, cache, export, glob, input, playback, plot, report, state, units};
+
+mod repl;
+use repl::{LoopStatus, Repl};
+
+const MAX_GRID: usize = 16;
+const MIN_RUN: usize = 16;
+const MAX_RUNS: usize = 10;
+
+// The plot range of samples that start at `origin` of the decoded stream.
+fn window<'a>(out: &'a [i16], origin: usize, opt: &Opts) -> &'a [i16] {
+    let to = opt.to.saturating_sub(origin).min(out.len());
+    &out[opt.from.saturating_sub(origin).min(to)..to]
+}
+
+// Block markers and labelled sections as cue points, and the loop between a
+// loop start and the loop end after it.
+fn cues(input: &[u8], opt: &Opts, sections: &[(String, usize, usize)]) -> export::Cues {
+    let markers = decode::markers(input, opt);
+    let mut points: Vec<export::Cue> = markers
+        .iter()
+        .map(|m| export::Cue {
+            position: m.index,
+            label: match m.kind {
+                MarkerKind::LoopStart => "loop start",
+                MarkerKind::LoopEnd => "loop end",
+                MarkerKind::End => "end",
+            }
+            .to_string(),
+            length: 0,
+        })
+        .collect();
+    points.extend(sections.iter().map(|(name, start, len)| export::Cue {
+        position: *start,
+        label: name.clone(),
+        length: *len,
+    }));
+    let sustain_loop = markers
+        .iter()
+        .find(|m| m.kind == MarkerKind::LoopStart)
+        .and_then(|start| {
+            markers
+                .iter()
+                .find(|m| m.kind == MarkerKind::LoopEnd && m.index > start.index)
+                .map(|end| (start.index, end.index))
+        });
+    export::Cues {
+        points,
+        sustain_loop,
+    }
+}
+
+// How many blocks had invalid headers and what was done with them, from the
+// offsets decode::bad_blocks lists.
+fn bad_blocks_note(bad: &[usize], opt: &Opts) -> Option<String> {
+    let first = bad.first()?;
+    let action = match opt.on_error {
+        OnError::Skip => "replaced by silence",
+        OnError::Clamp => "decoded with clamped headers",
+        OnError::Stop => "decoding stopped at the first",
+    };
+    Some(format!(
+        "warning: {} invalid {} blocks, first at offset {:#x}, {}",
+        bad.len(),
+        opt.compression,
+        first,
+        action
+    ))
+}
+
+// What the current settings decode to. A windowed view only decodes its
+// range, so its length is worked out instead, and with max-samples it holds
+// fewer samples than that.
+fn status_line(input: &[u8], out: &[i16], origin: usize, opt: &Opts) -> String {
+    let channels = decode::channels(opt);
+    let len = if opt.windowed {
+        decode::decoded_len(input.len(), opt)
+    } else {
+        out.len()
+    };
+    let frames = len / channels;
+    let mut line = format!(
+        "{} samples, {:.3}s at {} Hz",
+        frames,
+        frames as f64 / opt.rate as f64,
+        opt.rate
+    );
+    if out.len() < len {
+        line += &format!(", holding {} from {}", out.len() / channels, origin);
+    }
+    line
+}
+
+// The input byte behind a sample and what it decodes to.
+fn readout(
+    index: usize,
+    input: &[u8],
+    out: &[i16],
+    origin: usize,
+    opt: &Opts,
+    base: usize,
+) -> String {
+    let ix = decode::input_index(index, input.len(), opt);
+    let o = index.checked_sub(origin).and_then(|i| out.get(i));
+    match (input.get(ix), o) {
+        (Some(&d8), Some(o)) => format!(
+            "{}: input[{}] = {} (0x{:02x}), d = {}, out = {}",
+            index,
+            base + ix,
+            d8,
+            d8,
+            decode::represent(decode::prepare(d8, opt), opt),
+            o
+        ),
+        _ if origin > 0 => format!(
+            "{} is outside the decoded samples {}..{}",
+            index,
+            origin,
+            origin + out.len()
+        ),
+        _ => format!("{} is past the end ({} samples)", index, out.len()),
+    }
+}
+
+fn zoom_in(opt: &mut Opts, min_width: usize) {
+    let (from, to) = (opt.from, opt.to);
+    let width = to.saturating_sub(from);
+    if width <= min_width {
+        println!("already at the minimum width of {} samples", min_width);
+        return;
+    }
+    let amount = (width / 4).min((width - min_width) / 2);
+    opt.from = from + amount;
+    opt.to = to - amount;
+}
+
+fn zoom_out(opt: &mut Opts) {
+    let amount = opt.to.saturating_sub(opt.from) / 2;
+    opt.from -= amount.min(opt.from);
+    opt.to += amount;
+}
+
+fn move_left(opt: &mut Opts) {
+    let w = opt.to.saturating_sub(opt.from);
+    opt.from -= (w / 2).min(opt.from);
+    opt.to = opt.from + w;
+}
+
+fn move_right(opt: &mut Opts) {
+    let w = opt.to.saturating_sub(opt.from);
+    opt.from += w / 2;
+    opt.to = opt.from + w;
+}
+
+// Starts from the middle of the view when there is no cursor yet.
+fn move_cursor(view: &mut ViewOpts, opt: &Opts, by: isize) {
+    let index = view.cursor.unwrap_or((opt.from + opt.to) / 2);
+    view.cursor = Some(index.saturating_add_signed(by));
+}
+
+// Whether both settings write the same file; the view range, the selected
+// channel and how the view is decoded change nothing in the export.
+fn same_export(a: &Opts, b: &Opts) -> bool {
+    let strip = |opt: &Opts| Opts {
+        from: 0,
+        to: 0,
+        zcr_threshold: 0.0,
+        windowed: false,
+        max_samples: None,
+        channel: 0,
+        ..opt.clone()
+    };
+    strip(a) == strip(b)
+}
+
+// What to do on the way out with settings that changed since the last save
+// or export: write the output, the settings, both, or neither.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Leave {
+    Export,
+    Save,
+    Both,
+    Stay,
+    Discard,
+}
+
+// Settings can only be saved next to a single input.
+fn confirm_leave(out_filename: &str, settings: Option<&Path>) -> anyhow::Result<Leave> {
+    match settings {
+        Some(path) => print!(
+            "Settings changed since the last save or export, export {}? [Y/s/b/n/d] \
+             (s saves the settings to {} instead, b does both, n returns, d exits \
+             without either) ",
+            out_filename,
+            state::sidecar(path).display()
+        ),
+        None => print!(
+            "Settings changed since the last export, export {}? [Y/n/d] (n returns, \
+             d exits without exporting) ",
+            out_filename
+        ),
+    }
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_ascii_lowercase().as_str() {
+        "s" if settings.is_some() => Leave::Save,
+        "b" if settings.is_some() => Leave::Both,
+        "n" => Leave::Stay,
+        "d" => Leave::Discard,
+        _ => Leave::Export,
+    })
+}
+
+fn parse_offset(s: &str) -> anyhow::Result<u64> {
+    Ok(match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
 }
 
-#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone)]
-#[display(style = "lowercase")]
-enum Compression {
-    DPCM0,
-    DPCM1,
-    DPCM2,
-    DPCM3,
-    DPCMROQ,
-    DPCMSDX,
+fn opts_from_matches(matches: &ArgMatches, mut opts: Opts) -> anyhow::Result<Opts> {
+    if let Some(v) = matches.value_of("REPRESENTATION") {
+        opts.representation = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("COMPRESSION") {
+        opts.compression = v.parse()?;
+    }
+    if let Some(values) = matches.values_of("STAGE") {
+        for v in values {
+            opts.stages.push(v.parse()?);
+        }
+    }
+    if let Some(v) = matches.value_of("TABLE_FILE") {
+        opts.table = decode::load_table(Path::new(v))?;
+    }
+    if let Some(v) = matches.value_of("K") {
+        opts.k = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("FLIP") {
+        opts.flip = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("MIRROR") {
+        opts.mirror = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("FOLD") {
+        opts.fold = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("SIGN") {
+        opts.sign = v.parse()?;
+    }
+    if matches.is_present("NIBBLE_SWAP") {
+        opts.nibble_swap = true;
+    }
+    if let Some(v) = matches.value_of("MASK") {
+        opts.mask = decode::parse_hex(v)?;
+    }
+    if matches.is_present("MASK_SHIFT") {
+        opts.mask_shift = true;
+    }
+    if let Some(v) = matches.value_of("NIBBLE_ORDER") {
+        opts.nibble_order = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("CREATIVE_BITS") {
+        opts.creative_bits = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("ADAPTIVE") {
+        opts.adaptive = v.parse()?;
+    }
+    if matches.is_present("NO_SDX_RESET") {
+        opts.sdx_reset = false;
+    }
+    if let Some(v) = matches.value_of("ROQ_SHIFT") {
+        opts.roq_shift = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("DPCM0_GAIN") {
+        opts.dpcm0_gain = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("FRAME_SIZE") {
+        opts.frame_size = match v {
+            "native" => None,
+            v => Some(v.parse()?),
+        };
+    }
+    if matches.is_present("BYTE_PLANE_16") {
+        opts.byte_planes = true;
+    }
+    if let Some(v) = matches.value_of("PLANE_BOUNDARY") {
+        opts.plane_boundary = match v {
+            "mid" => None,
+            v => Some(v.parse()?),
+        };
+    }
+    if let Some(v) = matches.value_of("ON_ERROR") {
+        opts.on_error = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("PARTIAL") {
+        opts.partial = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("STEP") {
+        opts.step = decode::parse_step(v)?;
+    }
+    if let Some(v) = matches.value_of("SKIP") {
+        opts.skip = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("RATE") {
+        opts.rate = units::parse_rate(v)?;
+    }
+    if matches.is_present("INTERLEAVED") {
+        opts.stereo = true;
+    }
+    if matches.is_present("PLANAR_STEREO") {
+        opts.stereo = true;
+        opts.planar = true;
+    }
+    if matches.is_present("DOWNMIX") {
+        opts.downmix = true;
+    }
+    if matches.is_present("UPMIX") {
+        opts.upmix = true;
+    }
+    if let Some(v) = matches.value_of("OVERSAMPLE") {
+        opts.oversample = v.parse::<u32>()?.max(1);
+    }
+    if let Some(v) = matches.value_of("SAMPLE_FORMAT") {
+        opts.sample_format = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("FORMAT") {
+        opts.file_format = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("DITHER") {
+        opts.dither = v.parse()?;
+    }
+    if matches.is_present("EMBED_MARKERS") {
+        opts.embed_markers = true;
+    }
+    if let Some(v) = matches.value_of("WARMUP") {
+        opts.warmup = match v {
+            "auto" => None,
+            v => Some(v.parse()?),
+        };
+    }
+    if let Some(v) = matches.value_of("MAX_SAMPLES") {
+        opts.max_samples = Some(v.parse()?);
+        opts.windowed = true;
+    }
+    if matches.is_present("SETTLE") {
+        opts.settle = true;
+    }
+    if let Some(v) = matches.value_of("ZCR_THRESHOLD") {
+        opts.zcr_threshold = v.parse()?;
+    }
+    if let Some(v) = matches.value_of("HIGHPASS") {
+        opts.highpass = v.parse()?;
+    }
+    if let Some(mut values) = matches.values_of("BANDPASS") {
+        let low = values.next().unwrap().parse()?;
+        let high = values.next().unwrap().parse()?;
+        opts.bandpass = Some((low, high));
+    }
+    if let Some(v) = matches.value_of("GATE") {
+        opts.gate = v.parse()?;
+    }
+    Ok(opts)
 }
 
-#[derive(Copy, Clone, Debug)]
-struct Opts {
-    from: usize,
-    to: usize,
-    step: usize,
-    skip: usize,
-    k: u8,
-    flip: u8,
-    mirror: u8,
-    sign: u8,
-    representation: Representation,
-    compression: Compression,
+fn quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./,:".contains(c))
+    {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
 }
 
-impl Default for Opts {
-    fn default() -> Opts {
-        Opts {
-            from: 0,
-            to: 8192,
-            step: 1,
-            skip: 0,
-            k: 0,
-            flip: 0,
-            mirror: 0,
-            sign: 1,
-            representation: Representation::TwosComplement,
-            compression: Compression::DPCM0,
+// The flags that opts_from_matches would turn back into opt, skipping the
+// ones left at their defaults.
+fn args_from_opts(opt: &Opts, table_file: Option<&str>) -> Vec<String> {
+    let default = Opts::default();
+    let mut args = vec![];
+    let mut flag = |name: &str, value: String| {
+        args.push(format!("--{}", name));
+        if !value.is_empty() {
+            args.push(quote(&value));
         }
+    };
+    if opt.representation != default.representation {
+        flag("representation", opt.representation.to_string());
+    }
+    if opt.compression != default.compression {
+        flag("compression", opt.compression.to_string());
+    }
+    for stage in &opt.stages {
+        flag("stage", stage.to_string());
+    }
+    if let (false, Some(path)) = (opt.table.is_empty(), table_file) {
+        flag("table-file", path.to_string());
+    }
+    if opt.k != default.k {
+        flag("k", opt.k.to_string());
+    }
+    if opt.flip != default.flip {
+        flag("flip", opt.flip.to_string());
+    }
+    if opt.mirror != default.mirror {
+        flag("mirror", opt.mirror.to_string());
+    }
+    if opt.fold != default.fold {
+        flag("fold", opt.fold.to_string());
+    }
+    if opt.sign != default.sign {
+        flag("sign", opt.sign.to_string());
+    }
+    if opt.nibble_swap {
+        flag("nibble-swap", String::new());
+    }
+    if opt.mask != default.mask {
+        flag("mask", format!("{:02x}", opt.mask));
+    }
+    if opt.mask_shift {
+        flag("mask-shift", String::new());
+    }
+    if opt.nibble_order != default.nibble_order {
+        flag("nibble-order", opt.nibble_order.to_string());
+    }
+    if opt.creative_bits != default.creative_bits {
+        flag("creative-bits", opt.creative_bits.to_string());
+    }
+    if opt.adaptive != default.adaptive {
+        flag("adaptive", opt.adaptive.to_string());
+    }
+    if !opt.sdx_reset {
+        flag("no-sdx-reset", String::new());
+    }
+    if opt.roq_shift != default.roq_shift {
+        flag("roq-shift", opt.roq_shift.to_string());
     }
+    if opt.dpcm0_gain != default.dpcm0_gain {
+        flag("dpcm0-gain", opt.dpcm0_gain.to_string());
+    }
+    if let Some(size) = opt.frame_size {
+        flag("frame-size", size.to_string());
+    }
+    if opt.byte_planes {
+        flag("byte-plane-16", String::new());
+    }
+    if let Some(boundary) = opt.plane_boundary {
+        flag("plane-boundary", boundary.to_string());
+    }
+    if opt.on_error != default.on_error {
+        flag("on-error", opt.on_error.to_string());
+    }
+    if opt.partial != default.partial {
+        flag("partial", opt.partial.to_string());
+    }
+    if opt.step != default.step {
+        flag("step", opt.step.to_string());
+    }
+    if opt.skip != default.skip {
+        flag("skip", opt.skip.to_string());
+    }
+    if opt.rate != default.rate {
+        flag("rate", opt.rate.to_string());
+    }
+    if opt.stereo && opt.planar {
+        flag("planar-stereo", String::new());
+    } else if opt.stereo {
+        flag("interleaved", String::new());
+    }
+    if opt.downmix {
+        flag("downmix", String::new());
+    }
+    if opt.upmix {
+        flag("upmix", String::new());
+    }
+    if opt.oversample != default.oversample {
+        flag("oversample", opt.oversample.to_string());
+    }
+    if opt.sample_format != default.sample_format {
+        flag("sample-format", opt.sample_format.to_string());
+    }
+    if opt.file_format != default.file_format {
+        flag("format", opt.file_format.to_string());
+    }
+    if opt.dither != default.dither {
+        flag("dither", opt.dither.to_string());
+    }
+    if opt.embed_markers {
+        flag("embed-markers", String::new());
+    }
+    if let Some(warmup) = opt.warmup {
+        flag("warmup", warmup.to_string());
+    }
+    if let Some(max) = opt.max_samples {
+        flag("max-samples", max.to_string());
+    }
+    if opt.settle {
+        flag("settle", String::new());
+    }
+    if opt.zcr_threshold != default.zcr_threshold {
+        flag("zcr-threshold", opt.zcr_threshold.to_string());
+    }
+    if opt.highpass != default.highpass {
+        flag("highpass", opt.highpass.to_string());
+    }
+    if opt.gate != default.gate {
+        flag("gate", opt.gate.to_string());
+    }
+    if let Some((low, high)) = opt.bandpass {
+        args.extend(["--bandpass".into(), low.to_string(), high.to_string()]);
+    }
+    args
 }
 
-fn main() -> anyhow::Result<()> {
-    env_logger::init();
+// Argument help listing every variant, written the same way as "wrap|clamp".
+fn choices<T: std::fmt::Display>(variants: impl Iterator<Item = T>) -> String {
+    variants
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join("|")
+}
 
-    let host = cpal::default_host();
-    let device = host.default_output_device().unwrap();
-    let config = device.default_output_config().unwrap();
+// The full invocation that reproduces the current decode.
+fn command_line(inputs: &[PathBuf], opt: &Opts, table_file: Option<&str>) -> String {
+    let mut args = vec!["pcm-extract".to_string()];
+    args.extend(
+        inputs
+            .iter()
+            .flat_map(|p| ["-i".into(), quote(&p.to_string_lossy())]),
+    );
+    args.extend(args_from_opts(opt, table_file));
+    args.join(" ")
+}
 
-    let matches = App::new("PCM Extract")
+fn app() -> App<'static, 'static> {
+    App::new("PCM Extract")
         .version("0.1")
         .author("Pär Bohrarper <par@bohrarper.se>")
         .about("Extract PCM samples")
@@ -78,229 +565,1995 @@ fn main() -> anyhow::Result<()> {
             Arg::with_name("INPUT")
                 .short("i")
                 .long("input")
-                .help("Sets the input file to use")
-                .required(true)
+                .help("Sets the input file to use (may be a glob pattern like 'roms/*.snd')")
+                .required_unless_one(&["BATCH_DIR", "PROJECT", "GENERATE_COMPLETIONS"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("PROJECT")
+                .long("project")
+                .help("Opens a project file saved with save-project, with its inputs unless -i is given")
+                .conflicts_with("BATCH_DIR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("OUTPUT")
+                .short("o")
+                .long("output")
+                .help("Sets the output file to use, - writes raw samples to stdout")
+                .required_unless_one(&["BATCH_DIR", "OUT_DIR", "STDOUT", "GENERATE_COMPLETIONS"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("STDOUT")
+                .long("stdout")
+                .help("Writes raw 16-bit samples to stdout without the REPL")
+                .conflicts_with_all(&["OUTPUT", "OUT_DIR"]),
+        )
+        .arg(
+            Arg::with_name("RAW_ENDIAN")
+                .long("raw-endian")
+                .help("Sets the byte order of raw output: little (default) or big")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("RESUME")
+                .long("resume")
+                .help("Restores and saves the settings used for the input in a sidecar file")
+                .conflicts_with_all(&["BATCH_DIR", "OUT_DIR", "STDOUT"]),
+        )
+        .arg(
+            Arg::with_name("CACHE_DIR")
+                .long("cache-dir")
+                .help("Keeps decoded output in this directory and reuses it for the same input and settings")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("MMAP")
+                .long("mmap")
+                .help("Memory-maps the input file instead of reading it into memory; the file must not change while it is open (unix only, read as usual elsewhere)"),
+        )
+        .arg(
+            Arg::with_name("RANGE_BYTES")
+                .long("range-bytes")
+                .help("Reads only <start> <len> bytes of the input (decimal or 0x hex)")
+                .number_of_values(2)
+                .value_names(&["start", "len"])
+                .conflicts_with("MMAP")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TOC")
+                .long("toc")
+                .help("Describes a table of offset/length pairs in the input as offset,entries[,u16le|u16be|u32le|u32be]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TRACK")
+                .long("track")
+                .help("Reads only this entry of the --toc table")
+                .requires("TOC")
+                .conflicts_with("RANGE_BYTES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ABSOLUTE_OFFSETS")
+                .long("absolute-offsets")
+                .help("Reports input offsets from the start of the file instead of the range")
+                .requires("RANGE_BYTES"),
+        )
+        .arg(
+            Arg::with_name("BATCH_DIR")
+                .long("batch-dir")
+                .help("Converts every file in a directory without starting the REPL")
+                .requires("OUT_DIR")
+                .conflicts_with_all(&["INPUT", "OUTPUT"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("OUT_DIR")
+                .long("out-dir")
+                .help("Sets the output directory for batch conversion")
+                .conflicts_with("OUTPUT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("OUT_TEMPLATE")
+                .long("out-template")
+                .help("Sets the batch output file name, e.g. \"{stem}_{rate}hz.wav\" (default {stem}.{format})")
+                .requires("OUT_DIR")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DRY_RUN")
+                .long("dry-run")
+                .help("Prints what batch conversion would write without doing it")
+                .requires("OUT_DIR"),
+        )
+        .arg(
+            Arg::with_name("TIMING")
+                .long("timing")
+                .help("Prints how long decoding and plotting took after each redraw"),
+        )
+        .arg(
+            Arg::with_name("JOBS")
+                .long("jobs")
+                .short("j")
+                .help("Sets how many files or sections are converted at once (default one per core)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("REPRESENTATION")
+                .long("representation")
+                .help("Sets the representation")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("COMPRESSION")
+                .long("compression")
+                .help("Sets the compression")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("STAGE")
+                .long("stage")
+                .help("Adds a compression stage applied after the main one (repeatable)")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("TABLE_FILE")
+                .long("table-file")
+                .help("Loads a 16- or 256-entry delta table for tabledpcm")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("K")
+                .long("k")
+                .help("Sets k, the bias subtracted by excess-k and custom, which may be negative")
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("FLIP")
+                .long("flip")
+                .help("Sets flip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("MIRROR")
+                .long("mirror")
+                .help("Sets mirror")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("FOLD")
+                .long("fold")
+                .help("Sets whether custom values past the signed 8-bit range wrap or clamp")
+                .possible_values(&["wrap", "clamp"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SIGN")
+                .long("sign")
+                .help("Sets the sign bit of signedmagnitude: 0 (LSB), 1 (MSB) or bit0..bit7")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("NIBBLE_SWAP")
+                .long("nibble-swap")
+                .help("Swaps the high and low nibble of each byte"),
+        )
+        .arg(
+            Arg::with_name("MASK")
+                .long("mask")
+                .help("Sets a hex mask ANDed with each byte before decoding")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("MASK_SHIFT")
+                .long("mask-shift")
+                .help("Shifts masked bytes down to the least significant bits"),
+        )
+        .arg(
+            Arg::with_name("NIBBLE_ORDER")
+                .long("nibble-order")
+                .help("Sets which nibble is decoded first (native, high or low)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("CREATIVE_BITS")
+                .long("creative-bits")
+                .help("Sets the Creative ADPCM code size: 4, 3 (2.6-bit) or 2")
+                .possible_values(&["2", "3", "4"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ADAPTIVE")
+                .long("adaptive")
+                .help("Sets the adaptation rate and min and max step of adaptivedpcm as rate,min,max")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("NO_SDX_RESET")
+                .long("no-sdx-reset")
+                .help("Makes even dpcmsdx codes add to the previous sample instead of starting from zero"),
+        )
+        .arg(
+            Arg::with_name("ROQ_SHIFT")
+                .long("roq-shift")
+                .help("Shifts the squared dpcmroq delta right by this many bits")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DPCM0_GAIN")
+                .long("dpcm0-gain")
+                .help("Sets the left shift dpcm0 widens samples by (8 by default), or auto to fit the representation to full scale")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("FRAME_SIZE")
+                .long("frame-size")
+                .help("Sets the block size in bytes of brr, psxadpcm and appleima4, each block then decoded on its own, or native")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("BYTE_PLANE_16")
+                .long("byte-plane-16")
+                .help("Reads 16-bit samples stored as all low bytes followed by all high bytes"),
+        )
+        .arg(
+            Arg::with_name("PLANE_BOUNDARY")
+                .long("plane-boundary")
+                .help("Sets where the high bytes of --byte-plane-16 start, in bytes after skip, or mid")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("PARTIAL")
+                .long("partial")
+                .help("Drops or zero-pads a trailing block or byte-plane sample cut off by the end of the input")
+                .possible_values(&["drop", "pad"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ON_ERROR")
+                .long("on-error")
+                .help("Sets what happens to blocks with invalid headers: skip, clamp or stop")
+                .possible_values(&["skip", "clamp", "stop"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("STEP")
+                .long("step")
+                .help("Sets the stride in whole samples (bytes) between decoded samples")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SKIP")
+                .long("skip")
+                .help("Sets skip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("RATE")
+                .long("rate")
+                .help("Sets the sample rate in Hz, e.g. 16000 or 44.1k")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("WARMUP")
+                .long("warmup")
+                .help("Sets how many samples before the view decode-view primes the decoder with, or auto")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("MAX_SAMPLES")
+                .long("max-samples")
+                .help("Decodes only the plot range, at most this many samples, of files that decode to more")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SETTLE")
+                .long("settle")
+                .help("Fades in over the warmup length to hide the transient of zero-primed predictors"),
+        )
+        .arg(
+            Arg::with_name("ZCR_THRESHOLD")
+                .long("zcr-threshold")
+                .help("Sets the zero crossings per sample below which stats reports voiced")
                 .takes_value(true),
         )
-        .arg(
-            Arg::with_name("OUTPUT")
-                .short("o")
-                .long("output")
-                .help("Sets the output file to use")
-                .required(true)
-                .takes_value(true),
+        .arg(
+            Arg::with_name("HIGHPASS")
+                .long("highpass")
+                .help("Sets a high-pass filter cutoff in Hz for decoded output (0 is off)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("BANDPASS")
+                .long("bandpass")
+                .help("Sets band-pass filter corners in Hz for decoded output")
+                .number_of_values(2)
+                .value_names(&["low", "high"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("GATE")
+                .long("gate")
+                .help("Zeroes decoded output quieter than this amplitude (0 is off)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("INTERLEAVED")
+                .long("interleaved")
+                .help("Decodes the input as interleaved stereo"),
+        )
+        .arg(
+            Arg::with_name("PLANAR_STEREO")
+                .long("planar-stereo")
+                .help("Decodes the first half of the input as left and the second as right")
+                .conflicts_with("INTERLEAVED"),
+        )
+        .arg(
+            Arg::with_name("DOWNMIX")
+                .long("downmix")
+                .help("Averages stereo output to one channel on export"),
+        )
+        .arg(
+            Arg::with_name("UPMIX")
+                .long("upmix")
+                .help("Copies mono output to both channels on export"),
+        )
+        .arg(
+            Arg::with_name("OVERSAMPLE")
+                .long("oversample")
+                .help("Interpolates exported WAV files to this multiple of the rate")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("SAMPLE_FORMAT")
+                .long("sample-format")
+                .help("Sets the sample format of exported WAV files")
+                .possible_values(&["u8", "i16", "f32"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("FORMAT")
+                .long("format")
+                .help("Sets the file format of exports: wav or flac (ogg and opus are not built in)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("DITHER")
+                .long("dither")
+                .help("Sets the dither added before reducing exports to 8 bits")
+                .possible_values(&["none", "tpdf"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("EMBED_MARKERS")
+                .long("embed-markers")
+                .help("Writes block loop markers and sections as WAV cue points, with a smpl chunk for the loop (WAV only, full exports only)"),
+        )
+        .arg(
+            Arg::with_name("GENERATE_COMPLETIONS")
+                .long("generate-completions")
+                .help("Prints a completion script for the given shell")
+                .possible_values(&Shell::variants())
+                .hidden(true)
+                .takes_value(true),
+        )
+}
+
+fn main() -> anyhow::Result<()> {
+    // The logger itself passes everything so that the log command can raise the
+    // level past what RUST_LOG asked for at startup.
+    let level = env_logger::Builder::from_default_env().build().filter();
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Trace)
+        .init();
+    log::set_max_level(level);
+
+    let mut app = app();
+    let matches = app.clone().get_matches();
+
+    if let Some(shell) = matches.value_of("GENERATE_COMPLETIONS") {
+        app.gen_completions_to(
+            "pcm-extract",
+            shell.parse().unwrap(),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+
+    if let Some(jobs) = matches.value_of("JOBS") {
+        let jobs: usize = jobs.parse()?;
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+
+    let project = match matches.value_of("PROJECT") {
+        Some(path) => Some(state::load_project(Path::new(path))?),
+        None => None,
+    };
+    let inputs = if let Some(batch_dir) = matches.value_of("BATCH_DIR") {
+        batch::list_dir(Path::new(batch_dir))?
+    } else if let (Some(project), false) = (&project, matches.is_present("INPUT")) {
+        if project.inputs.is_empty() {
+            anyhow::bail!("the project names no input, give one with -i");
+        }
+        project.inputs.clone()
+    } else {
+        let input = matches.value_of("INPUT").unwrap();
+        if glob::is_pattern(input) && !Path::new(input).exists() {
+            glob::expand(input)?
+        } else {
+            vec![PathBuf::from(input)]
+        }
+    };
+
+    let single = match inputs.as_slice() {
+        [path] => Some(path.clone()),
+        _ => None,
+    };
+    let resume = single.clone().filter(|_| matches.is_present("RESUME"));
+    let mut saved = project
+        .as_ref()
+        .map_or_else(Opts::default, |p| p.opt.clone());
+    let mut view = project
+        .as_ref()
+        .map_or_else(ViewOpts::default, |p| p.view.clone());
+    if let Some(path) = &resume {
+        if state::load(path, &mut saved, &mut view)? {
+            println!("restored settings from {}", state::sidecar(path).display());
+        }
+    }
+    let opts = opts_from_matches(&matches, saved)?;
+
+    if let Some(out_dir) = matches.value_of("OUT_DIR") {
+        let template = matches
+            .value_of("OUT_TEMPLATE")
+            .unwrap_or(batch::DEFAULT_TEMPLATE);
+        let dry_run = matches.is_present("DRY_RUN");
+        return Ok(batch::run(
+            &inputs,
+            Path::new(out_dir),
+            template,
+            &opts,
+            dry_run,
+        )?);
+    }
+
+    let mut base = 0;
+    let mut input = match matches.values_of("RANGE_BYTES") {
+        Some(mut values) => {
+            let start = parse_offset(values.next().unwrap())?;
+            let len = parse_offset(values.next().unwrap())?;
+            if matches.is_present("ABSOLUTE_OFFSETS") {
+                base = start as usize;
+            }
+            input::load_range(&inputs, start, len)?
+        }
+        None => input::load(&inputs, matches.is_present("MMAP"))?,
+    };
+    let toc_format = matches
+        .value_of("TOC")
+        .map(str::parse::<TocFormat>)
+        .transpose()?
+        .or(project.as_ref().and_then(|p| p.toc));
+    let toc_format = match (toc_format, matches.value_of("TRACK")) {
+        (Some(format), Some(n)) => {
+            let n: usize = n.parse()?;
+            let tracks = toc::read(&input, &format)?;
+            let track = match tracks.get(n) {
+                Some(track) => *track,
+                None => anyhow::bail!("no track {}, the table has {}", n, tracks.len()),
+            };
+            // readouts keep giving offsets into the whole file
+            base = track.offset;
+            input = input::Input::Owned(input[track.offset..][..track.len].to_vec());
+            // the table describes the whole file, not this track
+            None
+        }
+        (format, _) => format,
+    };
+
+    let cache = matches
+        .value_of("CACHE_DIR")
+        .map(|dir| (PathBuf::from(dir), cache::hash_input(&input)));
+    let decode_full = |opt: &Opts| match &cache {
+        Some((dir, hash)) => cache::decode_cached(dir, *hash, &input, opt),
+        None => decode(&input, opt),
+    };
+
+    if matches.is_present("STDOUT") || matches.value_of("OUTPUT") == Some("-") {
+        if let Some(note) = bad_blocks_note(&decode::bad_blocks(&input, &opts), &opts) {
+            eprintln!("{}", note);
+        }
+        let endian = match matches.value_of("RAW_ENDIAN") {
+            Some(v) => v.parse()?,
+            None => export::Endian::Little,
+        };
+        let mut stdout = std::io::stdout().lock();
+        // without a cache to fill, byte-wise decodes go out as they are made
+        let written = if cache.is_none() && decode::decodes_lazily(&opts) {
+            export::write_raw(&mut stdout, decode::decode_iter(&input, &opts), endian)
+        } else {
+            export::write_raw(&mut stdout, decode_full(&opts), endian)
+        };
+        return match written {
+            // the reader closing the pipe early is not an error
+            Err(PcmError::Io { source, .. }) if source.kind() == std::io::ErrorKind::BrokenPipe => {
+                Ok(())
+            }
+            r => Ok(r?),
+        };
+    }
+
+    let mut player = playback::Player::default();
+
+    let opt_ref = RefCell::new(opts.clone());
+    let view_ref = RefCell::new(view);
+    // the settings the last save or export wrote; leaving with others asks
+    let saved_ref = RefCell::new(opts.clone());
+    let export_output = RefCell::new(false);
+    let play = RefCell::new(false);
+    let stop = RefCell::new(false);
+    let seek = RefCell::new(None);
+    let pause = RefCell::new(None);
+    let value = RefCell::new(None);
+    let ab = RefCell::new(None);
+    let stats = RefCell::new(false);
+    let detect_rate = RefCell::new(false);
+    let runs = RefCell::new(false);
+    let detect_depth = RefCell::new(false);
+    let info = RefCell::new(false);
+    let scrub = RefCell::new(false);
+    let grid = RefCell::new(None);
+    let (saved_sections, saved_ranges) = match project {
+        Some(p) => (p.sections, p.ranges),
+        None => (vec![], vec![]),
+    };
+    let ranges = RefCell::new(saved_ranges);
+    let list_ranges = RefCell::new(false);
+    let export_ranges = RefCell::new(None);
+    let sections: RefCell<Vec<(String, usize, usize)>> = RefCell::new(saved_sections);
+    let list_sections = RefCell::new(false);
+    let export_sections = RefCell::new(None);
+    let segment = RefCell::new(None);
+    let toc_format = RefCell::new(toc_format);
+    let list_tracks = RefCell::new(false);
+    let describe = RefCell::new(false);
+    let status = RefCell::new(false);
+    let select_track = RefCell::new(None);
+    let table_file = RefCell::new(matches.value_of("TABLE_FILE").map(String::from));
+    let print_args = RefCell::new(false);
+    let spectrum = RefCell::new(false);
+    let overview = RefCell::new(false);
+    let csv = RefCell::new(None);
+    let report = RefCell::new(None);
+    let marks = RefCell::new(false);
+    let export_loop = RefCell::new(None);
+    let mut out;
+    let mut origin;
+
+    let mut repl = Repl::builder()
+        .add(
+            "flip",
+            easy_repl::Command {
+                description: "Set flip".into(),
+                args_info: vec![],
+                handler: Box::new(|args| {
+                    let validator = validator!(u8);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().flip = args[0].parse::<u8>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "mirror",
+            easy_repl::Command {
+                description: "Set mirror".into(),
+                args_info: vec![],
+                handler: Box::new(|args| {
+                    let validator = validator!(u8);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().mirror = args[0].parse::<u8>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "fold",
+            easy_repl::Command {
+                description: "Set whether custom values wrap or clamp".into(),
+                args_info: vec!["wrap|clamp".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(Fold);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().fold = args[0].parse::<Fold>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "sign",
+            easy_repl::Command {
+                description: "Set the sign bit of signedmagnitude (0=LSB, 1=MSB, bitN)".into(),
+                args_info: vec!["0|1|bitN".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(SignBit);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().sign = args[0].parse::<SignBit>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "nibble-swap",
+            easy_repl::Command {
+                description: "Toggle swapping the high and low nibble of each byte".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let nibble_swap = !opt_ref.borrow_mut().target().nibble_swap;
+                    opt_ref.borrow_mut().target().nibble_swap = nibble_swap;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "mask",
+            easy_repl::Command {
+                description: "Set a hex mask ANDed with each byte, optionally shifted down".into(),
+                args_info: vec!["hex".into(), "[shift]".into()],
+                handler: Box::new(|args| {
+                    let shift = match args {
+                        [_] => false,
+                        [_, "shift"] => true,
+                        _ => return Err(anyhow::anyhow!("usage: mask <hex> [shift]")),
+                    };
+                    opt_ref.borrow_mut().target().mask = decode::parse_hex(args[0])?;
+                    opt_ref.borrow_mut().target().mask_shift = shift;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "nibble-order",
+            easy_repl::Command {
+                description: "Set which nibble nibble-based decoders read first".into(),
+                args_info: vec![
+                    NibbleOrder::Native.to_string(),
+                    NibbleOrder::High.to_string(),
+                    NibbleOrder::Low.to_string(),
+                ],
+                handler: Box::new(|args| {
+                    let validator = validator!(NibbleOrder);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().nibble_order = args[0].parse::<NibbleOrder>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "k",
+            easy_repl::Command {
+                description: "Set k, the bias subtracted by excess-k and custom".into(),
+                args_info: vec!["-32768..32767".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(i16);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().k = args[0].parse::<i16>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "excess128",
+            easy_repl::Command {
+                description: "Read bytes as unsigned 8-bit centered at 128 (excess-k with k 128)"
+                    .into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let mut opt = opt_ref.borrow_mut();
+                    let target = opt.target();
+                    target.representation = Representation::ExcessK;
+                    target.k = 128;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "representation",
+            easy_repl::Command {
+                description: "Set representation".into(),
+                args_info: vec![choices(Representation::all())],
+                handler: Box::new(|args| {
+                    let validator = validator!(Representation);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().representation =
+                        args[0].parse::<Representation>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "compression",
+            easy_repl::Command {
+                description: "Set compression".into(),
+                args_info: vec![choices(Compression::all())],
+                handler: Box::new(|args| {
+                    let validator = validator!(Compression);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().compression = args[0].parse::<Compression>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "describe",
+            easy_repl::Command {
+                description: "Explain the selected compression and representation".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *describe.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "creative-bits",
+            easy_repl::Command {
+                description: "Set Creative ADPCM code size".into(),
+                args_info: vec!["4|3|2".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(u8);
+                    validator(args)?;
+                    let bits = args[0].parse::<u8>()?;
+                    if !(2..=4).contains(&bits) {
+                        println!("code size must be 4, 3 (2.6-bit) or 2");
+                    } else {
+                        opt_ref.borrow_mut().target().creative_bits = bits;
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "adaptive",
+            easy_repl::Command {
+                description: "Set how fast the adaptivedpcm step changes and its limits".into(),
+                args_info: vec!["rate".into(), "min".into(), "max".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(f64, u16, u16);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().adaptive = args.join(",").parse()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "sdx-reset",
+            easy_repl::Command {
+                description: "Toggle even dpcmsdx codes starting over from zero".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let sdx_reset = !opt_ref.borrow_mut().target().sdx_reset;
+                    opt_ref.borrow_mut().target().sdx_reset = sdx_reset;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "roq-shift",
+            easy_repl::Command {
+                description: "Set how many bits the squared dpcmroq delta is shifted right".into(),
+                args_info: vec!["bits".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(u8);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().roq_shift = args[0].parse()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "dpcm0-gain",
+            easy_repl::Command {
+                description: "Set the left shift dpcm0 widens samples by, or auto for full scale"
+                    .into(),
+                args_info: vec!["0..15|auto".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(Gain);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().dpcm0_gain = args[0].parse()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "frame-size",
+            easy_repl::Command {
+                description: "Set the block size of block formats, each decoded on its own".into(),
+                args_info: vec!["bytes|native".into()],
+                handler: Box::new(|args| {
+                    let size = match args {
+                        ["native"] => None,
+                        _ => {
+                            let validator = validator!(usize);
+                            validator(args)?;
+                            Some(args[0].parse::<usize>()?)
+                        }
+                    };
+                    opt_ref.borrow_mut().target().frame_size = size;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "byte-plane-16",
+            easy_repl::Command {
+                description: "Toggle reading 16-bit samples from a low and a high byte plane"
+                    .into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let byte_planes = !opt_ref.borrow().byte_planes;
+                    opt_ref.borrow_mut().byte_planes = byte_planes;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "plane-boundary",
+            easy_repl::Command {
+                description: "Set where the high byte plane starts, in bytes after skip".into(),
+                args_info: vec!["bytes|mid".into()],
+                handler: Box::new(|args| {
+                    let boundary = match args {
+                        ["mid"] => None,
+                        _ => {
+                            let validator = validator!(usize);
+                            validator(args)?;
+                            Some(args[0].parse::<usize>()?)
+                        }
+                    };
+                    opt_ref.borrow_mut().plane_boundary = boundary;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "partial",
+            easy_repl::Command {
+                description:
+                    "Set whether a block or sample cut off at the end is dropped or padded".into(),
+                args_info: vec![Partial::Drop.to_string(), Partial::Pad.to_string()],
+                handler: Box::new(|args| {
+                    let validator = validator!(Partial);
+                    validator(args)?;
+                    opt_ref.borrow_mut().partial = args[0].parse::<Partial>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "on-error",
+            easy_repl::Command {
+                description: "Set what block formats do with blocks with invalid headers".into(),
+                args_info: vec![
+                    OnError::Skip.to_string(),
+                    OnError::Clamp.to_string(),
+                    OnError::Stop.to_string(),
+                ],
+                handler: Box::new(|args| {
+                    let validator = validator!(OnError);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().on_error = args[0].parse::<OnError>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "stages",
+            easy_repl::Command {
+                description: "Set compression stages applied after the main one".into(),
+                args_info: vec!["[compression]...".into()],
+                handler: Box::new(|args| {
+                    let stages = args
+                        .iter()
+                        .map(|arg| arg.parse::<Compression>())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    opt_ref.borrow_mut().target().stages = stages;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "table-file",
+            easy_repl::Command {
+                description: "Load a 16- or 256-entry delta table for tabledpcm".into(),
+                args_info: vec!["path".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String);
+                    validator(args)?;
+                    opt_ref.borrow_mut().target().table = decode::load_table(Path::new(args[0]))?;
+                    *table_file.borrow_mut() = Some(args[0].to_string());
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "step",
+            easy_repl::Command {
+                description: "Set the stride in whole samples between decoded samples".into(),
+                args_info: vec![],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    opt_ref.borrow_mut().step = decode::parse_step(args[0])?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "skip",
+            easy_repl::Command {
+                description: "Set skip".into(),
+                args_info: vec![],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    opt_ref.borrow_mut().skip = args[0].parse::<usize>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "rate",
+            easy_repl::Command {
+                description: "Set sample rate".into(),
+                args_info: vec!["Hz|k|kHz".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String);
+                    validator(args)?;
+                    opt_ref.borrow_mut().rate = units::parse_rate(args[0])?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "highpass",
+            easy_repl::Command {
+                description: "Set high-pass filter cutoff in Hz, 0 turns it off".into(),
+                args_info: vec!["Hz".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(f64);
+                    validator(args)?;
+                    opt_ref.borrow_mut().highpass = args[0].parse::<f64>()?.max(0.0);
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "bandpass",
+            easy_repl::Command {
+                description: "Set band-pass filter corners in Hz, or off".into(),
+                args_info: vec!["low|off".into(), "high".into()],
+                handler: Box::new(|args| {
+                    let bandpass = match args {
+                        ["off"] => None,
+                        _ => {
+                            let validator = validator!(f64, f64);
+                            validator(args)?;
+                            let low = args[0].parse::<f64>()?;
+                            let high = args[1].parse::<f64>()?;
+                            if low >= high {
+                                println!("low must be below high");
+                                return Ok(CommandStatus::Done);
+                            }
+                            Some((low, high))
+                        }
+                    };
+                    opt_ref.borrow_mut().bandpass = bandpass;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "gate",
+            easy_repl::Command {
+                description: "Set noise gate threshold, 0 turns it off".into(),
+                args_info: vec![],
+                handler: Box::new(|args| {
+                    let validator = validator!(u16);
+                    validator(args)?;
+                    opt_ref.borrow_mut().gate = args[0].parse::<u16>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "range",
+            easy_repl::Command {
+                description: "Set plot range in samples, or with s/ms suffixes".into(),
+                args_info: vec!["from".into(), "to".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String, String);
+                    validator(args)?;
+                    let rate = opt_ref.borrow().rate;
+                    let from = units::parse_samples(args[0], rate)?;
+                    let to = units::parse_samples(args[1], rate)?;
+                    if from >= to {
+                        println!("from must be less than to");
+                    } else {
+                        opt_ref.borrow_mut().from = from;
+                        opt_ref.borrow_mut().to = to;
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "+",
+            easy_repl::Command {
+                description: "Zoom in".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let min_width = view_ref.borrow().min_width;
+                    zoom_in(&mut opt_ref.borrow_mut(), min_width);
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "-",
+            easy_repl::Command {
+                description: "Zoom out".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    zoom_out(&mut opt_ref.borrow_mut());
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "<",
+            easy_repl::Command {
+                description: "Move left".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    move_left(&mut opt_ref.borrow_mut());
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            ">",
+            easy_repl::Command {
+                description: "Move right".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    move_right(&mut opt_ref.borrow_mut());
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "scrub",
+            easy_repl::Command {
+                description: "Move and zoom with single keys until q is pressed".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *scrub.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "min-width",
+            easy_repl::Command {
+                description: "Set the narrowest plot range + zooms in to".into(),
+                args_info: vec!["samples".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    view_ref.borrow_mut().min_width = args[0].parse::<usize>()?.max(1);
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "value",
+            easy_repl::Command {
+                description: "Show input byte, decoded value and output at an index".into(),
+                args_info: vec!["index".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    *value.borrow_mut() = Some(args[0].parse::<usize>()?);
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "cursor",
+            easy_repl::Command {
+                description: "Mark a sample on the plot and show its values after each redraw"
+                    .into(),
+                args_info: vec!["index|off".into()],
+                handler: Box::new(|args| {
+                    let cursor = match args {
+                        ["off"] => None,
+                        _ => {
+                            let validator = validator!(String);
+                            validator(args)?;
+                            let rate = opt_ref.borrow().rate;
+                            Some(units::parse_samples(args[0], rate)?)
+                        }
+                    };
+                    view_ref.borrow_mut().cursor = cursor;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "stats",
+            easy_repl::Command {
+                description: "Show RMS, peak, DC and zero-crossing rate of the plot range".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *stats.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "grid",
+            easy_repl::Command {
+                description: format!(
+                    "Plot up to {} consecutive windows of the current width",
+                    MAX_GRID
+                ),
+                args_info: vec!["count".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    let count = args[0].parse::<usize>()?;
+                    if count == 0 || count > MAX_GRID {
+                        println!("count must be between 1 and {}", MAX_GRID);
+                    } else {
+                        *grid.borrow_mut() = Some(count);
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "detect-depth",
+            easy_repl::Command {
+                description: "Score 8- and 16-bit readings of the plot range by smoothness".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *detect_depth.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "detect-rate",
+            easy_repl::Command {
+                description: "Estimate the pitch of the plot range and suggest sample rates".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *detect_rate.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "runs",
+            easy_repl::Command {
+                description: "Show the longest runs of a repeated byte in the plot range".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *runs.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "zcr-threshold",
+            easy_repl::Command {
+                description: "Set zero crossings per sample below which stats reports voiced"
+                    .into(),
+                args_info: vec![],
+                handler: Box::new(|args| {
+                    let validator = validator!(f64);
+                    validator(args)?;
+                    opt_ref.borrow_mut().zcr_threshold = args[0].parse::<f64>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "logplot",
+            easy_repl::Command {
+                description: "Toggle logarithmic amplitude in the output plot".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let logplot = !view_ref.borrow().logplot;
+                    view_ref.borrow_mut().logplot = logplot;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "hires",
+            easy_repl::Command {
+                description: "Toggle larger, finer charts for wide terminals".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let hires = !view_ref.borrow().hires;
+                    view_ref.borrow_mut().hires = hires;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "yrange",
+            easy_repl::Command {
+                description: "Fix the vertical axis of the output plots".into(),
+                args_info: vec!["min".into(), "max".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(i32, i32);
+                    validator(args)?;
+                    let min = args[0].parse::<i32>()?;
+                    let max = args[1].parse::<i32>()?;
+                    if min >= max {
+                        println!("min must be below max");
+                    } else {
+                        let mut view = view_ref.borrow_mut();
+                        view.yrange = (min, max);
+                        view.autoscale = false;
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "autoscale",
+            easy_repl::Command {
+                description: "Toggle between autoscaling and the yrange axis".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let autoscale = !view_ref.borrow().autoscale;
+                    view_ref.borrow_mut().autoscale = autoscale;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "status",
+            easy_repl::Command {
+                description: "Show how many samples the current settings decode to".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *status.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "prompt-length",
+            easy_repl::Command {
+                description: "Toggle showing the decoded length above every prompt".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let prompt_length = !view_ref.borrow().prompt_length;
+                    view_ref.borrow_mut().prompt_length = prompt_length;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "center-input",
+            easy_repl::Command {
+                description: "Toggle plotting input bytes centered on zero instead of 0..255"
+                    .into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let center_input = !view_ref.borrow().center_input;
+                    view_ref.borrow_mut().center_input = center_input;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "overlay",
+            easy_repl::Command {
+                description: "Toggle drawing input and output on a single chart".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let overlay = !view_ref.borrow().overlay;
+                    view_ref.borrow_mut().overlay = overlay;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "clip-marks",
+            easy_repl::Command {
+                description: "Toggle marking samples clipped at the 16-bit limits".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let clip_marks = !view_ref.borrow().clip_marks;
+                    view_ref.borrow_mut().clip_marks = clip_marks;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "overview",
+            easy_repl::Command {
+                description: "Plot the min/max envelope of the whole file, marking the plot range"
+                    .into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *overview.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "spectrum",
+            easy_repl::Command {
+                description: "Plot the magnitude spectrum of the plot range".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *spectrum.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "window",
+            easy_repl::Command {
+                description: "Set the window function applied before the spectrum".into(),
+                args_info: vec![
+                    WindowFunction::Rect.to_string(),
+                    WindowFunction::Hann.to_string(),
+                    WindowFunction::Hamming.to_string(),
+                ],
+                handler: Box::new(|args| {
+                    let validator = validator!(WindowFunction);
+                    validator(args)?;
+                    view_ref.borrow_mut().window = args[0].parse::<WindowFunction>()?;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "meter",
+            easy_repl::Command {
+                description: "Toggle the peak/RMS meter during playback".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let meter = !view_ref.borrow().meter;
+                    view_ref.borrow_mut().meter = meter;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "envelope",
+            easy_repl::Command {
+                description: "Shape playback with an ADSR envelope (ms, ms, 0-1, ms), or off"
+                    .into(),
+                args_info: vec![
+                    "attack|off".into(),
+                    "decay".into(),
+                    "sustain".into(),
+                    "release".into(),
+                ],
+                handler: Box::new(|args| {
+                    let envelope = match args {
+                        ["off"] => None,
+                        _ => {
+                            let validator = validator!(f64, f64, f64, f64);
+                            validator(args)?;
+                            Some(args.join(",").parse::<playback::Envelope>()?)
+                        }
+                    };
+                    view_ref.borrow_mut().envelope = envelope;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "echo",
+            easy_repl::Command {
+                description: format!(
+                    "Add an echo (ms, feedback up to {}) to playback, or off",
+                    playback::MAX_FEEDBACK
+                ),
+                args_info: vec!["delay|off".into(), "feedback".into()],
+                handler: Box::new(|args| {
+                    let echo = match args {
+                        ["off"] => None,
+                        _ => {
+                            let validator = validator!(f64, f64);
+                            validator(args)?;
+                            Some(args.join(",").parse::<playback::Echo>()?)
+                        }
+                    };
+                    view_ref.borrow_mut().echo = echo;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "info",
+            easy_repl::Command {
+                description: "Show the input files, any known header and the implied length".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *info.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "interleaved",
+            easy_repl::Command {
+                description: "Toggle decoding the input as interleaved stereo".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let mut opt = opt_ref.borrow_mut();
+                    opt.stereo = !opt.stereo || opt.planar;
+                    opt.planar = false;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "planar-stereo",
+            easy_repl::Command {
+                description: "Toggle decoding the input halves as left and right".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let mut opt = opt_ref.borrow_mut();
+                    opt.stereo = !(opt.stereo && opt.planar);
+                    opt.planar = opt.stereo;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "stereo-split",
+            easy_repl::Command {
+                description: "Toggle separate decode settings for the right channel".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let mut opt = opt_ref.borrow_mut();
+                    if opt.right.is_some() {
+                        opt.right = None;
+                        opt.channel = 0;
+                        println!("both channels use the same settings");
+                    } else {
+                        opt.right = Some(Box::new(opt.clone()));
+                        println!("right channel settings split off, select with channel 1");
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "channel",
+            easy_repl::Command {
+                description: "Select the channel decode settings apply to (0 left, 1 right)".into(),
+                args_info: vec!["0|1".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    let channel = args[0].parse::<usize>()?;
+                    let mut opt = opt_ref.borrow_mut();
+                    if channel > 1 {
+                        println!("channel must be 0 or 1");
+                    } else if channel == 1 && opt.right.is_none() {
+                        println!(
+                            "use stereo-split first to give the right channel its own settings"
+                        );
+                    } else {
+                        opt.channel = channel;
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "decode-view",
+            easy_repl::Command {
+                description: "Toggle decoding only the plot range".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let windowed = !opt_ref.borrow().windowed;
+                    opt_ref.borrow_mut().windowed = windowed;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "max-samples",
+            easy_repl::Command {
+                description: "Set how many samples decode-view keeps when the file has more".into(),
+                args_info: vec!["samples|off".into()],
+                handler: Box::new(|args| {
+                    let max = match args {
+                        ["off"] => None,
+                        _ => {
+                            let validator = validator!(usize);
+                            validator(args)?;
+                            Some(args[0].parse::<usize>()?)
+                        }
+                    };
+                    opt_ref.borrow_mut().max_samples = max;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "settle",
+            easy_repl::Command {
+                description: "Toggle fading in over the warmup length at the start".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let settle = !opt_ref.borrow().settle;
+                    opt_ref.borrow_mut().settle = settle;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "warmup",
+            easy_repl::Command {
+                description: "Set samples decoded before the view to prime the predictor".into(),
+                args_info: vec!["samples|auto".into()],
+                handler: Box::new(|args| {
+                    let warmup = match args {
+                        ["auto"] => None,
+                        _ => {
+                            let validator = validator!(usize);
+                            validator(args)?;
+                            Some(args[0].parse::<usize>()?)
+                        }
+                    };
+                    opt_ref.borrow_mut().warmup = warmup;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "args",
+            easy_repl::Command {
+                description: "Print the command-line flags for the current settings".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *print_args.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "log",
+            easy_repl::Command {
+                description: "Set log level".into(),
+                args_info: vec!["off|error|warn|info|debug|trace".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(log::LevelFilter);
+                    validator(args)?;
+                    log::set_max_level(args[0].parse::<log::LevelFilter>()?);
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "exit",
+            easy_repl::Command {
+                description: "Quit repl".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| Ok(CommandStatus::Quit)),
+            },
+        )
+        .add(
+            "quit",
+            easy_repl::Command {
+                description: "Quit repl".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| Ok(CommandStatus::Quit)),
+            },
+        )
+        .add(
+            "save",
+            easy_repl::Command {
+                description: "Save the settings to the sidecar file of the input".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    let path = single.as_ref().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "settings are saved next to a single input; use save-project"
+                        )
+                    })?;
+                    let opt = opt_ref.borrow().clone();
+                    state::save(path, &opt, &view_ref.borrow())?;
+                    *saved_ref.borrow_mut() = opt;
+                    println!("saved {}", state::sidecar(path).display());
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "export",
+            easy_repl::Command {
+                description: "Write the output file now, as leaving does".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *export_output.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "export-csv",
+            easy_repl::Command {
+                description: "Write index, input byte, d and out of the plot range as CSV".into(),
+                args_info: vec!["path".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String);
+                    validator(args)?;
+                    *csv.borrow_mut() = Some(PathBuf::from(args[0]));
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "report",
+            easy_repl::Command {
+                description: "Write a text summary of the file, settings and plot range".into(),
+                args_info: vec!["path".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String);
+                    validator(args)?;
+                    *report.borrow_mut() = Some(PathBuf::from(args[0]));
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "add-range",
+            easy_repl::Command {
+                description: "Append a range to the selection for export-ranges".into(),
+                args_info: vec!["from".into(), "to".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String, String);
+                    validator(args)?;
+                    let rate = opt_ref.borrow().rate;
+                    let from = units::parse_samples(args[0], rate)?;
+                    let to = units::parse_samples(args[1], rate)?;
+                    if from >= to {
+                        println!("from must be less than to");
+                    } else {
+                        ranges.borrow_mut().push((from, to));
+                    }
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "ranges",
+            easy_repl::Command {
+                description: "List the ranges selected with add-range".into(),
+                args_info: vec![],
+                handler: Box::new(|_args| {
+                    *list_ranges.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
         )
-        .get_matches();
-
-    let filename = &matches.value_of("INPUT").unwrap();
-    let mut file = fs::File::open(Path::new(filename)).unwrap();
-    let mut input = vec![];
-    file.read_to_end(&mut input).unwrap();
-
-    let opt_ref = RefCell::new(Opts::default());
-    let play = RefCell::new(false);
-    let mut out = vec![];
-
-    let mut repl = Repl::builder()
         .add(
-            "flip",
+            "clear-ranges",
             easy_repl::Command {
-                description: "Set flip".into(),
+                description: "Clear the ranges selected with add-range".into(),
                 args_info: vec![],
+                handler: Box::new(|_args| {
+                    ranges.borrow_mut().clear();
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "export-ranges",
+            easy_repl::Command {
+                description: "Write the selected ranges, in order, to one WAV file".into(),
+                args_info: vec!["path".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(u8);
+                    let validator = validator!(String);
                     validator(args)?;
-                    opt_ref.borrow_mut().flip = args[0].parse::<u8>()?;
+                    *export_ranges.borrow_mut() = Some(PathBuf::from(args[0]));
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "mirror",
+            "section",
             easy_repl::Command {
-                description: "Set mirror".into(),
-                args_info: vec![],
+                description: "Label a section of the samples for export-sections".into(),
+                args_info: vec!["name".into(), "start".into(), "len".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(u8);
+                    let validator = validator!(String, String, String);
                     validator(args)?;
-                    opt_ref.borrow_mut().mirror = args[0].parse::<u8>()?;
+                    let rate = opt_ref.borrow().rate;
+                    let start = units::parse_samples(args[1], rate)?;
+                    let len = units::parse_samples(args[2], rate)?;
+                    let name = args[0].to_string();
+                    if name.contains(std::path::is_separator) {
+                        println!("name is used as a file name and cannot contain a separator");
+                    } else if len == 0 {
+                        println!("len must not be zero");
+                    } else {
+                        let mut sections = sections.borrow_mut();
+                        // redefining a label moves it
+                        sections.retain(|(other, _, _)| *other != name);
+                        sections.push((name, start, len));
+                    }
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "sign",
+            "segment",
             easy_repl::Command {
-                description: "Set sign bit (0=LSB, 1=MSB)".into(),
-                args_info: vec![],
+                description: "Label the sounds between silent gaps as sections seg1, seg2, ..."
+                    .into(),
+                args_info: vec!["threshold".into(), "min-gap".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(u8);
+                    let validator = validator!(u16, String);
                     validator(args)?;
-                    opt_ref.borrow_mut().sign = args[0].parse::<u8>()?;
+                    let rate = opt_ref.borrow().rate;
+                    let threshold = args[0].parse::<u16>()?;
+                    let gap = units::parse_samples(args[1], rate)?;
+                    *segment.borrow_mut() = Some((threshold, gap));
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "k",
+            "save-project",
             easy_repl::Command {
-                description: "Set k".into(),
-                args_info: vec![],
+                description: "Save the inputs, settings, sections, ranges and toc to a file".into(),
+                args_info: vec!["path".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(u8);
+                    let validator = validator!(String);
                     validator(args)?;
-                    opt_ref.borrow_mut().k = args[0].parse::<u8>()?;
+                    let project = state::Project {
+                        // absolute, so the project opens from anywhere
+                        inputs: inputs
+                            .iter()
+                            .map(|p| fs::canonicalize(p).unwrap_or_else(|_| p.clone()))
+                            .collect(),
+                        opt: opt_ref.borrow().clone(),
+                        view: view_ref.borrow().clone(),
+                        sections: sections.borrow().clone(),
+                        ranges: ranges.borrow().clone(),
+                        toc: *toc_format.borrow(),
+                    };
+                    state::save_project(Path::new(args[0]), &project)?;
+                    *saved_ref.borrow_mut() = project.opt;
+                    println!("saved {}", args[0]);
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "representation",
+            "open-project",
             easy_repl::Command {
-                description: "Set representation".into(),
-                args_info: vec![
-                    Representation::SignedMagnitude.to_string(),
-                    Representation::OnesComplement.to_string(),
-                    Representation::TwosComplement.to_string(),
-                    Representation::Custom.to_string(),
-                ],
+                description: "Restore the settings, sections, ranges and toc of a project file"
+                    .into(),
+                args_info: vec!["path".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(Representation);
+                    let validator = validator!(String);
                     validator(args)?;
-                    opt_ref.borrow_mut().representation = args[0].parse::<Representation>()?;
+                    let project = state::load_project(Path::new(args[0]))?;
+                    let same = project.inputs.len() == inputs.len()
+                        && project
+                            .inputs
+                            .iter()
+                            .zip(&inputs)
+                            .all(|(a, b)| fs::canonicalize(a).ok() == fs::canonicalize(b).ok());
+                    if !same {
+                        println!(
+                            "the project is for other inputs; run with --project {} to open them",
+                            args[0]
+                        );
+                    }
+                    *opt_ref.borrow_mut() = project.opt;
+                    *view_ref.borrow_mut() = project.view;
+                    *sections.borrow_mut() = project.sections;
+                    *ranges.borrow_mut() = project.ranges;
+                    *toc_format.borrow_mut() = project.toc;
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "compression",
+            "toc",
             easy_repl::Command {
-                description: "Set compression".into(),
-                args_info: vec![
-                    Compression::DPCM0.to_string(),
-                    Compression::DPCM1.to_string(),
-                    Compression::DPCM2.to_string(),
-                    Compression::DPCM3.to_string(),
-                    Compression::DPCMROQ.to_string(),
-                    Compression::DPCMSDX.to_string(),
-                ],
+                description: "Describe a table of offset/length pairs in the input".into(),
+                args_info: vec!["offset,entries[,u16le|u16be|u32le|u32be]".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(Compression);
+                    let validator = validator!(TocFormat);
                     validator(args)?;
-                    opt_ref.borrow_mut().compression = args[0].parse::<Compression>()?;
+                    *toc_format.borrow_mut() = Some(args[0].parse::<TocFormat>()?);
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "step",
+            "list-tracks",
             easy_repl::Command {
-                description: "Set step".into(),
+                description: "List the entries of the table described with toc".into(),
                 args_info: vec![],
+                handler: Box::new(|_args| {
+                    *list_tracks.borrow_mut() = true;
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "track",
+            easy_repl::Command {
+                description: "Decode from a track of the toc table, viewing it as section trackN"
+                    .into(),
+                args_info: vec!["n".into()],
                 handler: Box::new(|args| {
                     let validator = validator!(usize);
                     validator(args)?;
-                    opt_ref.borrow_mut().step = args[0].parse::<usize>()?;
+                    *select_track.borrow_mut() = Some(args[0].parse::<usize>()?);
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "skip",
+            "sections",
             easy_repl::Command {
-                description: "Set skip".into(),
+                description: "List the sections labelled with section".into(),
                 args_info: vec![],
-                handler: Box::new(|args| {
-                    let validator = validator!(usize);
-                    validator(args)?;
-                    opt_ref.borrow_mut().skip = args[0].parse::<usize>()?;
+                handler: Box::new(|_args| {
+                    *list_sections.borrow_mut() = true;
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "range",
+            "clear-sections",
             easy_repl::Command {
-                description: "Set plot range".into(),
+                description: "Clear the sections labelled with section".into(),
                 args_info: vec![],
+                handler: Box::new(|_args| {
+                    sections.borrow_mut().clear();
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "export-sections",
+            easy_repl::Command {
+                description: "Write each section to its own file, named by its label, in dir"
+                    .into(),
+                args_info: vec!["dir".into()],
                 handler: Box::new(|args| {
-                    let validator = validator!(usize, usize);
+                    let validator = validator!(String);
                     validator(args)?;
-                    opt_ref.borrow_mut().from = args[0].parse::<usize>()?;
-                    opt_ref.borrow_mut().to = args[1].parse::<usize>()?;
+                    *export_sections.borrow_mut() = Some(PathBuf::from(args[0]));
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "+",
+            "marks",
             easy_repl::Command {
-                description: "Zoom in".into(),
+                description: "List loop and end markers found in block headers".into(),
                 args_info: vec![],
                 handler: Box::new(|_args| {
-                    let from = opt_ref.borrow().from;
-                    let to = opt_ref.borrow().to;
-                    let amount = (to - from) / 4;
-                    opt_ref.borrow_mut().from = from + amount;
-                    opt_ref.borrow_mut().to = to - amount;
+                    *marks.borrow_mut() = true;
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "-",
+            "export-loop",
             easy_repl::Command {
-                description: "Zoom out".into(),
+                description: "Write the samples between the loop markers as WAV".into(),
+                args_info: vec!["path".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(String);
+                    validator(args)?;
+                    *export_loop.borrow_mut() = Some(PathBuf::from(args[0]));
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "ab",
+            easy_repl::Command {
+                description: "Play range with current settings, then with another pair".into(),
+                args_info: vec!["representation".into(), "compression".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(Representation, Compression);
+                    validator(args)?;
+                    *ab.borrow_mut() = Some((
+                        args[0].parse::<Representation>()?,
+                        args[1].parse::<Compression>()?,
+                    ));
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "play",
+            easy_repl::Command {
+                description: "Play range in the background".into(),
                 args_info: vec![],
                 handler: Box::new(|_args| {
-                    let from = opt_ref.borrow().from;
-                    let to = opt_ref.borrow().to;
-                    let amount = (to - from) / 2;
-                    opt_ref.borrow_mut().from = from - amount.min(from);
-                    opt_ref.borrow_mut().to = to + amount;
+                    *play.borrow_mut() = true;
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "<",
+            "stop",
             easy_repl::Command {
-                description: "Move left".into(),
+                description: "Stop playback".into(),
                 args_info: vec![],
                 handler: Box::new(|_args| {
-                    let from = opt_ref.borrow().from;
-                    let to = opt_ref.borrow().to;
-                    let w = to - from;
-                    let amount = w / 2;
-                    opt_ref.borrow_mut().from = from - amount.min(from);
-                    opt_ref.borrow_mut().to = from - amount.min(from) + w;
+                    *stop.borrow_mut() = true;
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            ">",
+            "pause",
             easy_repl::Command {
-                description: "Move right".into(),
+                description: "Pause playback at the current position".into(),
                 args_info: vec![],
                 handler: Box::new(|_args| {
-                    let from = opt_ref.borrow().from;
-                    let to = opt_ref.borrow().to;
-                    let w = to - from;
-                    let amount = w / 2;
-                    opt_ref.borrow_mut().from = from + amount;
-                    opt_ref.borrow_mut().to = from + amount + w;
+                    *pause.borrow_mut() = Some(true);
                     Ok(CommandStatus::Done)
                 }),
             },
         )
         .add(
-            "play",
+            "resume",
             easy_repl::Command {
-                description: "Play range".into(),
+                description: "Resume paused playback".into(),
                 args_info: vec![],
                 handler: Box::new(|_args| {
-                    *play.borrow_mut() = true;
+                    *pause.borrow_mut() = Some(false);
+                    Ok(CommandStatus::Done)
+                }),
+            },
+        )
+        .add(
+            "seek",
+            easy_repl::Command {
+                description: "Move playback to a sample index within the played range".into(),
+                args_info: vec!["index".into()],
+                handler: Box::new(|args| {
+                    let validator = validator!(usize);
+                    validator(args)?;
+                    *seek.borrow_mut() = Some(args[0].parse::<usize>()?);
                     Ok(CommandStatus::Done)
                 }),
             },
@@ -308,181 +2561,696 @@ fn main() -> anyhow::Result<()> {
         .build()
         .expect("Failed to create repl");
 
+    let out_filename = matches.value_of("OUTPUT").unwrap();
+    let write_output = |opt: &Opts, out: &[i16]| -> anyhow::Result<String> {
+        let cues = cues(&input, opt, &sections.borrow());
+        // the windowed view never held the whole output, so stream it out
+        // instead of decoding it all here when the export allows
+        if opt.windowed && export::streams(opt) {
+            let path = Path::new(out_filename);
+            return Ok(export::export_streamed(path, &input, opt, &cues)?);
+        }
+        let full;
+        let out = if opt.windowed {
+            full = decode_full(opt);
+            &full
+        } else {
+            out
+        };
+        Ok(export::export_marked(
+            Path::new(out_filename),
+            out,
+            opt,
+            &cues,
+        )?)
+    };
+
+    let timing = matches.is_present("TIMING");
+    let mut raw: Option<RawMode> = None;
+    let mut bad_note = None;
+    let mut leave;
     loop {
         let opt = opt_ref.borrow().clone();
-        let mut ix = opt.skip;
-        out.clear();
-        loop {
-            let mut d8 = input[ix];
-            let d = match opt.representation {
-                Representation::Custom => {
-                    let f = opt.flip;
-                    let m = opt.mirror;
-                    if d8 > m {
-                        d8 = m + d8.overflowing_sub(m).0;
-                    }
-                    if d8 < f {
-                        d8 = f.overflowing_sub(d8).0;
-                    }
-                    let d = (d8 as i8) as i16;
-                    d.overflowing_sub(opt.k as i16).0
-                }
-                Representation::OnesComplement => {
-                    if d8 < 128 {
-                        d8 as i16
+        let view = view_ref.borrow().clone();
+        if raw.is_some() {
+            // redraw in place like a waveform viewer
+            print!("\x1b[2J\x1b[H");
+        }
+        let started = Instant::now();
+        (origin, out) = if opt.windowed {
+            decode::decode_bounded(&input, &opt)
+        } else {
+            (0, decode_full(&opt))
+        };
+        let bad = decode::bad_blocks(&input, &opt);
+        let decoded = Instant::now();
+
+        plot::draw(&out, origin, &input, &opt, &view);
+
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let decode_ms = ms(decoded - started);
+        let plot_ms = ms(decoded.elapsed());
+        let phases = format!(
+            "decode {:.1} ms ({} samples, {:.1} M/s), plot {:.1} ms",
+            decode_ms,
+            out.len(),
+            out.len() as f64 / decode_ms / 1000.0,
+            plot_ms
+        );
+        log::debug!("{}", phases);
+        if timing {
+            println!("timing: {}", phases);
+        }
+
+        // once per change rather than on every redraw
+        let note = bad_blocks_note(&bad, &opt);
+        if note != bad_note {
+            if let Some(note) = &note {
+                println!("{}", note);
+            }
+            bad_note = note;
+        }
+
+        if let Some(index) = value.borrow_mut().take() {
+            println!("index {}", readout(index, &input, &out, origin, &opt, base));
+        }
+
+        if let Some(index) = view.cursor {
+            println!(
+                "cursor: {} ({:.4}s)",
+                readout(index, &input, &out, origin, &opt, base),
+                index as f64 / opt.rate as f64
+            );
+        }
+
+        if *stats.borrow() {
+            *stats.borrow_mut() = false;
+            let s = analysis::stats(window(&out, origin, &opt));
+            println!(
+                "rms: {:.1} ({:.1} dBFS), peak: {} ({:.1} dBFS), dc: {:.1}, zcr: {:.4} -> {}",
+                s.rms,
+                analysis::dbfs(s.rms),
+                s.peak,
+                analysis::dbfs(s.peak as f64),
+                s.dc,
+                s.zcr,
+                analysis::classify(&s, opt.zcr_threshold)
+            );
+        }
+
+        if *print_args.borrow() {
+            *print_args.borrow_mut() = false;
+            println!(
+                "{}",
+                command_line(&inputs, &opt, table_file.borrow().as_deref())
+            );
+            let default = Opts::default();
+            if (opt.from, opt.to) != (default.from, default.to) {
+                println!("view {}..{} has no flag", opt.from, opt.to);
+            }
+            if opt.right.is_some() {
+                println!("separate right channel settings have no flags");
+            }
+        }
+
+        if let Some(count) = grid.borrow_mut().take() {
+            let width = opt.to.saturating_sub(opt.from);
+            let len = decode::decoded_len(input.len(), &opt);
+            let starts: Vec<usize> = (0..count)
+                .map(|i| opt.from + i * width)
+                .filter(|&start| start < len)
+                .collect();
+            let decoded: Vec<Vec<i16>> = starts
+                .iter()
+                .map(|&start| {
+                    let end = (start + width).min(len);
+                    if opt.windowed {
+                        decode::decode_range(&input, &opt, start, end, decode::warmup(&opt))
                     } else {
-                        -(!d8 as i16)
+                        out[start..end.min(out.len())].to_vec()
                     }
-                }
-                Representation::TwosComplement => (d8 as i8) as i16,
-                Representation::SignedMagnitude => {
-                    if opt.sign == 0 {
-                        let sign = d8 & 0x1;
-                        if sign == 0 {
-                            ((d8 & 0xFE) >> 1) as i16
-                        } else {
-                            -(((d8 & 0xFE) >> 1) as i16)
-                        }
-                    } else {
-                        let sign = d8 >> 7;
-                        if sign == 0 {
-                            (d8 & 0x7F) as i16
-                        } else {
-                            -((d8 & 0x7F) as i16)
-                        }
+                })
+                .collect();
+            let tiles: Vec<(usize, &[i16])> = starts
+                .iter()
+                .zip(&decoded)
+                .map(|(&start, samples)| (start, samples.as_slice()))
+                .collect();
+            plot::draw_grid(&tiles, width, &view);
+        }
+
+        if *detect_rate.borrow() {
+            *detect_rate.borrow_mut() = false;
+            match analysis::pitch_period(window(&out, origin, &opt)) {
+                None => println!("no clear period in the plot range"),
+                Some(period) => {
+                    println!("period: {:.2} samples", period);
+                    for rate in analysis::COMMON_RATES {
+                        let freq = rate as f64 / period;
+                        let (note, note_freq, cents) = analysis::nearest_note(freq);
+                        println!(
+                            "{:>6} Hz: f0 {:>8.1} Hz, {:<4} {:+4.0} cents (exact at {:.0} Hz){}",
+                            rate,
+                            freq,
+                            note,
+                            cents,
+                            note_freq * period,
+                            if (85.0..=255.0).contains(&freq) {
+                                ", speech range"
+                            } else {
+                                ""
+                            }
+                        );
                     }
                 }
-                Representation::ExcessK => (d8 as i16).overflowing_sub(opt.k as i16).0,
-            };
-            match opt.compression {
-                Compression::DPCM0 => out.push(d.saturating_mul(256)),
-                Compression::DPCM1 => {
-                    let err = d8;
-                    let n1: i16 = if out.len() > 0 { out[out.len() - 1] } else { 0 };
-                    if err < 128 {
-                        out.push(n1.saturating_add(err as i16));
-                    } else {
-                        out.push(n1.saturating_sub((err - 128) as i16));
-                    }
+            }
+        }
+
+        if *info.borrow() {
+            *info.borrow_mut() = false;
+            for path in &inputs {
+                match fs::metadata(path) {
+                    Ok(meta) => println!("{}: {} bytes", path.display(), meta.len()),
+                    Err(err) => println!("{}: {}", path.display(), err),
                 }
-                Compression::DPCM2 => {
-                    let err = d;
-                    let n1: i16 = if out.len() > 0 { out[out.len() - 1] } else { 0 };
-                    let n2: i16 = if out.len() > 1 { out[out.len() - 2] } else { 0 };
-                    out.push(n1.saturating_mul(2).saturating_sub(n2).saturating_add(err));
+            }
+            println!("loaded: {} bytes", input.len());
+            match input::sniff(&input) {
+                Some(format) => println!("header: {}", format),
+                None => println!("header: none recognized"),
+            }
+            let channels = decode::channels(&opt);
+            let frames = decode::decoded_len(input.len(), &opt) / channels;
+            println!(
+                "{} {} samples in {} channel(s), {:.3}s at {} Hz",
+                opt.compression,
+                frames,
+                channels,
+                frames as f64 / opt.rate as f64,
+                opt.rate
+            );
+        }
+
+        if *detect_depth.borrow() {
+            *detect_depth.borrow_mut() = false;
+            let start = decode::source_index(opt.from, &opt).min(input.len());
+            let end = decode::source_index(opt.to, &opt).clamp(start, input.len());
+            let scores = analysis::detect_depth(&input[start..end]);
+            for (depth, score) in &scores {
+                match score {
+                    Some(score) => println!("{:<21} {:.3}", depth.describe(), score),
+                    None => println!("{:<21} silent", depth.describe()),
                 }
-                Compression::DPCM3 => {
-                    let err = d;
-                    let n1: i16 = if out.len() > 0 { out[out.len() - 1] } else { 0 };
-                    let n2: i16 = if out.len() > 1 { out[out.len() - 2] } else { 0 };
-                    let n3: i16 = if out.len() > 2 { out[out.len() - 3] } else { 0 };
-                    out.push(
-                        n1.saturating_mul(3)
-                            .saturating_sub(n2.saturating_mul(3))
-                            .saturating_add(n3)
-                            .saturating_add(err),
-                    );
+            }
+            match scores[0] {
+                (_, None) => println!("no signal in the plot range"),
+                (_, Some(score)) if score > analysis::NOISE_ROUGHNESS => {
+                    println!("every reading looks like noise, so not plain PCM")
                 }
-                Compression::DPCMROQ => {
-                    let err = d8;
-                    let mut n1: i16 = if out.len() > 0 { out[out.len() - 1] } else { 0 };
-                    if err < 128 {
-                        out.push(n1.saturating_add(err as i16 * err as i16));
-                    } else {
-                        out.push(n1.saturating_sub((err - 128) as i16 * (err - 128) as i16));
-                    }
+                (Depth::Signed8, _) => println!("suggest: representation twos-complement"),
+                (Depth::Unsigned8, _) => {
+                    println!("suggest: representation excess-k with k 128")
                 }
-                Compression::DPCMSDX => {
-                    let n = d8 as i16;
-                    let mut n1: i16 = if out.len() > 0 { out[out.len() - 1] } else { 0 };
-                    if d8 & 1 == 0 {
-                        n1 = 0;
+                // the high byte of each sample is a fair 8-bit preview
+                (Depth::Little16, _) => println!("suggest: step 2 with skip {}", opt.skip + 1),
+                (Depth::Big16, _) => println!("suggest: step 2 with skip {}", opt.skip),
+            }
+        }
+
+        if *runs.borrow() {
+            *runs.borrow_mut() = false;
+            let start = decode::source_index(opt.from, &opt).min(input.len());
+            let end = decode::source_index(opt.to, &opt).clamp(start, input.len());
+            let found = analysis::runs(&input[start..end], MIN_RUN);
+            if found.is_empty() {
+                println!("no runs of {} or more bytes", MIN_RUN);
+            }
+            for run in found.iter().take(MAX_RUNS) {
+                println!(
+                    "0x{:02x} x {} at {}..{}",
+                    run.byte,
+                    run.len,
+                    base + start + run.start,
+                    base + start + run.start + run.len
+                );
+            }
+        }
+
+        if *overview.borrow() {
+            *overview.borrow_mut() = false;
+            if opt.windowed {
+                plot::draw_overview(&decode_full(&opt), &opt, &view);
+            } else {
+                plot::draw_overview(&out, &opt, &view);
+            }
+        }
+
+        if *spectrum.borrow() {
+            *spectrum.borrow_mut() = false;
+            let mags = analysis::spectrum(window(&out, origin, &opt), view.window);
+            plot::draw_spectrum(&mags, &opt, &view);
+        }
+
+        if let Some(path) = csv.borrow_mut().take() {
+            match export::write_csv(&path, &input, &out, origin, &opt) {
+                Ok(rows) => println!("wrote {} rows to {}", rows, path.display()),
+                Err(err) => println!("Error: {:#}", err),
+            }
+        }
+
+        if let Some(path) = report.borrow_mut().take() {
+            let text = report::text(&report::Report {
+                inputs: &inputs,
+                input_len: input.len(),
+                command: command_line(&inputs, &opt, table_file.borrow().as_deref()),
+                opt: &opt,
+                decoded_len: decode::decoded_len(input.len(), &opt),
+                window: window(&out, origin, &opt),
+                markers: decode::markers(&input, &opt),
+                ranges: &ranges.borrow(),
+            });
+            match std::fs::write(&path, text) {
+                Ok(()) => println!("wrote {}", path.display()),
+                Err(err) => println!("Error: {:#}", err),
+            }
+        }
+
+        if *list_ranges.borrow() {
+            *list_ranges.borrow_mut() = false;
+            let ranges = ranges.borrow();
+            if ranges.is_empty() {
+                println!("no ranges selected");
+            }
+            for (i, (from, to)) in ranges.iter().enumerate() {
+                println!("{}: {}..{} ({} samples)", i, from, to, to - from);
+            }
+        }
+
+        if let Some(path) = export_ranges.borrow_mut().take() {
+            let full;
+            let samples: &[i16] = if opt.windowed {
+                full = decode_full(&opt);
+                &full
+            } else {
+                &out
+            };
+            let mut joined = vec![];
+            for &(from, to) in ranges.borrow().iter() {
+                let to = to.min(samples.len());
+                joined.extend_from_slice(&samples[from.min(to)..to]);
+            }
+            match export::export(&path, &joined, &opt) {
+                Ok(summary) => println!("wrote {}", summary),
+                Err(err) => println!("Error: {:#}", err),
+            }
+        }
+
+        if let Some((threshold, gap)) = segment.borrow_mut().take() {
+            let full;
+            let samples: &[i16] = if opt.windowed {
+                full = decode_full(&opt);
+                &full
+            } else {
+                &out
+            };
+            // whole frames, so stereo sections keep their channel order
+            let channels = decode::channels(&opt);
+            let sounds = analysis::sounds(samples, threshold, gap);
+            let mut sections = sections.borrow_mut();
+            for (i, &(start, len)) in sounds.iter().enumerate() {
+                let name = format!("seg{}", i + 1);
+                let first = start / channels * channels;
+                let len = (start + len).div_ceil(channels) * channels - first;
+                sections.retain(|(other, _, _)| *other != name);
+                sections.push((name, first, len));
+            }
+            println!("{} sounds found, use sections to see them", sounds.len());
+        }
+
+        if *describe.borrow() {
+            *describe.borrow_mut() = false;
+            let mut shown = opt.clone();
+            let o = shown.target();
+            println!("{}: {}", o.compression, o.compression.describe());
+            if o.compression.uses_representation() {
+                println!("{}: {}", o.representation, o.representation.describe());
+            } else {
+                println!(
+                    "{} reads the bytes itself, the representation is unused",
+                    o.compression
+                );
+            }
+        }
+
+        let selected = select_track.borrow_mut().take();
+        if *list_tracks.borrow() || selected.is_some() {
+            *list_tracks.borrow_mut() = false;
+            let tracks = match &*toc_format.borrow() {
+                Some(format) => toc::read(&input, format),
+                None => Ok(vec![]),
+            };
+            match tracks {
+                Ok(tracks) if tracks.is_empty() => println!("no tracks, describe them with toc"),
+                Ok(tracks) => {
+                    // samples each track decodes to with this track's bytes only
+                    let samples = |t: &toc::Track| {
+                        let o = Opts {
+                            skip: t.offset,
+                            ..opt.clone()
+                        };
+                        decode::decoded_len(t.offset + t.len, &o)
+                    };
+                    match selected {
+                        Some(n) => match tracks.get(n) {
+                            Some(t) => {
+                                let len = samples(t);
+                                let mut o = opt_ref.borrow_mut();
+                                o.skip = t.offset;
+                                o.from = 0;
+                                o.to = len.max(1);
+                                let name = format!("track{}", n);
+                                let mut sections = sections.borrow_mut();
+                                sections.retain(|(other, _, _)| *other != name);
+                                sections.push((name, 0, len));
+                                println!("skip {} for track {}, {} samples", t.offset, n, len);
+                            }
+                            None => println!("no track {}, the table has {}", n, tracks.len()),
+                        },
+                        None => {
+                            for (i, t) in tracks.iter().enumerate() {
+                                println!(
+                                    "{}: offset {} (0x{:x}), {} bytes, {} samples",
+                                    i,
+                                    base + t.offset,
+                                    base + t.offset,
+                                    t.len,
+                                    samples(t)
+                                );
+                            }
+                        }
                     }
-                    let sq = n * n * 2;
-                    if n < 0 {
-                        out.push(n1.saturating_add(sq as i16));
-                    } else {
-                        out.push(n1.saturating_sub(sq as i16));
+                }
+                Err(err) => println!("Error: {:#}", err),
+            }
+        }
+
+        if *list_sections.borrow() {
+            *list_sections.borrow_mut() = false;
+            let sections = sections.borrow();
+            if sections.is_empty() {
+                println!("no sections labelled");
+            }
+            for (name, start, len) in sections.iter() {
+                println!(
+                    "{}: {}..{} ({} samples, {:.3}s)",
+                    name,
+                    start,
+                    start + len,
+                    len,
+                    *len as f64 / opt.rate as f64
+                );
+            }
+        }
+
+        if let Some(dir) = export_sections.borrow_mut().take() {
+            let full;
+            let samples: &[i16] = if opt.windowed {
+                full = decode_full(&opt);
+                &full
+            } else {
+                &out
+            };
+            if let Err(err) = fs::create_dir_all(&dir) {
+                println!("Error: failed to create {}: {}", dir.display(), err);
+            } else {
+                // encoded side by side, reported in the order they were labelled
+                let results: Vec<_> = sections
+                    .borrow()
+                    .par_iter()
+                    .map(|(name, start, len)| {
+                        let end = (start + len).min(samples.len());
+                        let path = dir.join(format!("{}.{}", name, opt.file_format));
+                        export::export(&path, &samples[(*start).min(end)..end], &opt)
+                    })
+                    .collect();
+                for result in results {
+                    match result {
+                        Ok(summary) => println!("wrote {}", summary),
+                        Err(err) => println!("Error: {:#}", err),
                     }
                 }
             }
-            if ix < 10 {
-                println!("d: {}, out: {}", d, out.last().unwrap());
+        }
+
+        if *marks.borrow() {
+            *marks.borrow_mut() = false;
+            let markers = decode::markers(&input, &opt);
+            if markers.is_empty() {
+                println!("no markers");
             }
-            ix += opt.step;
-            if ix >= input.len() {
-                break;
+            for m in markers {
+                println!(
+                    "{:?} at {} ({:.3}s)",
+                    m.kind,
+                    m.index,
+                    m.index as f64 / opt.rate as f64
+                );
             }
         }
 
-        let mut plt = vec![];
-        for (i, x) in out.iter().enumerate() {
-            plt.push((i as f32, *x as f32));
+        if export_output.replace(false) {
+            match write_output(&opt, &out) {
+                Ok(summary) => {
+                    *saved_ref.borrow_mut() = opt.clone();
+                    println!("wrote {}", summary);
+                }
+                Err(err) => println!("Error: {:#}", err),
+            }
         }
-        Chart::new(300, 60, opt.from as f32, opt.to as f32)
-            .lineplot(&Shape::Steps(&plt))
-            .display();
-        let mut plt2 = vec![];
-        for (i, x) in input.iter().skip(opt.skip).step_by(opt.step).enumerate() {
-            plt2.push((i as f32, *x as f32));
+
+        if let Some(path) = export_loop.borrow_mut().take() {
+            let full;
+            let out: &[i16] = if opt.windowed {
+                full = decode_full(&opt);
+                &full
+            } else {
+                &out
+            };
+            let markers = decode::markers(&input, &opt);
+            let start = markers
+                .iter()
+                .find(|m| m.kind == MarkerKind::LoopStart)
+                .map_or(0, |m| m.index)
+                .min(out.len());
+            let end = markers
+                .iter()
+                .find(|m| m.kind != MarkerKind::LoopStart && m.index > start)
+                .map_or(out.len(), |m| m.index)
+                .min(out.len());
+            match export::export(&path, &out[start..end], &opt) {
+                Ok(summary) => println!("wrote {}", summary),
+                Err(err) => println!("Error: {:#}", err),
+            }
         }
-        Chart::new(300, 60, opt.from as f32, opt.to as f32)
-            .lineplot(&Shape::Steps(&plt2))
-            .display();
 
         if *play.borrow() {
             *play.borrow_mut() = false;
 
-            let barrier = Arc::new(Barrier::new(2));
-
-            let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
-
-            let out_copy = out.clone();
-            let from = opt.from.min(out_copy.len());
-            let to = opt.to.min(out_copy.len());
-            let mut frames = 0;
-
-            let c = Arc::clone(&barrier);
-            let mut done = false;
-            let sc: cpal::StreamConfig = config.clone().into();
-            let stream = device.build_output_stream(
-                &sc,
-                move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                    for frame in data.chunks_mut(sc.channels as usize) {
-                        // up-sample
-                        let ix = from + frames / 2;
-                        if ix < to {
-                            let value = cpal::Sample::from::<i16>(&out_copy[ix]);
-                            for sample in frame.iter_mut() {
-                                *sample = value;
-                            }
-                        } else if !done {
-                            done = true;
-                            println!("no more data!");
-                            c.wait();
-                        }
-                        frames += 1;
-                    }
-                },
-                err_fn,
-            )?;
-            stream.play()?;
-            println!("wait..");
-            barrier.wait();
-            println!("done!");
+            let samples = window(&out, origin, &opt).to_vec();
+            if let Err(err) = player.play(samples, opt.from, &opt, &view) {
+                println!("Error: {:#}", err);
+            }
+        }
+
+        if *stop.borrow() {
+            *stop.borrow_mut() = false;
+            if !player.stop() {
+                println!("nothing is playing");
+            }
+        }
+
+        if let Some(paused) = pause.borrow_mut().take() {
+            if let Err(err) = player.pause(paused) {
+                println!("Error: {:#}", err);
+            }
+        }
+
+        if let Some(index) = seek.borrow_mut().take() {
+            if let Err(err) = player.seek(index) {
+                println!("Error: {:#}", err);
+            }
+        }
+
+        if let Some((representation, compression)) = ab.borrow_mut().take() {
+            let opt_b = Opts {
+                representation,
+                compression,
+                ..opt.clone()
+            };
+            let (origin_b, out_b) = if opt.windowed {
+                decode::decode_bounded(&input, &opt_b)
+            } else {
+                (0, decode_full(&opt_b))
+            };
+            println!(
+                "A: {} {}, B: {} {}",
+                opt.representation, opt.compression, representation, compression
+            );
+            let mut samples = window(&out, origin, &opt).to_vec();
+            samples.extend(std::iter::repeat_n(0, opt.rate as usize / 4));
+            samples.extend_from_slice(window(&out_b, origin_b, &opt_b));
+            if let Err(err) = player.play(samples, opt.from, &opt, &view) {
+                println!("Error: {:#}", err);
+            }
+        }
+
+        if *scrub.borrow() {
+            *scrub.borrow_mut() = false;
+            match RawMode::enable() {
+                Ok(mode) => {
+                    println!(
+                        "scrubbing: arrows or < > move, up/down or + - zoom, , . move the \
+                         cursor, q returns"
+                    );
+                    raw = Some(mode);
+                }
+                Err(err) => println!("Error: {:#}", err),
+            }
+        }
+
+        if let Some(mode) = &raw {
+            let done = loop {
+                let mut opt = opt_ref.borrow_mut();
+                let mut view = view_ref.borrow_mut();
+                match mode.read() {
+                    Ok(Key::Left | Key::Char(b'<')) => move_left(&mut opt),
+                    Ok(Key::Right | Key::Char(b'>')) => move_right(&mut opt),
+                    Ok(Key::Up | Key::Char(b'+')) => zoom_in(&mut opt, view.min_width),
+                    Ok(Key::Down | Key::Char(b'-')) => zoom_out(&mut opt),
+                    Ok(Key::Char(b',')) => move_cursor(&mut view, &opt, -1),
+                    Ok(Key::Char(b'.')) => move_cursor(&mut view, &opt, 1),
+                    // Ctrl-C is a plain byte while scrubbing
+                    Ok(Key::Char(b'q') | Key::Escape | Key::Char(3)) | Err(_) => break true,
+                    Ok(_) => continue,
+                }
+                break false;
+            };
+            if done {
+                raw = None;
+            }
+            continue;
+        }
+
+        // easy-repl keeps the prompt it was built with, so the length goes on
+        // the line above it
+        if *status.borrow() || view.prompt_length {
+            *status.borrow_mut() = false;
+            println!("{}", status_line(&input, &out, origin, &opt));
         }
 
         if let Ok(LoopStatus::Continue) = repl.next() {
+            continue;
+        }
+        // exit, quit, Ctrl-C and the end of input all come here
+        leave = if same_export(&opt_ref.borrow(), &saved_ref.borrow()) {
+            Leave::Export
         } else {
+            confirm_leave(out_filename, single.as_deref())?
+        };
+        if leave != Leave::Stay {
             break;
         }
     }
 
-    let out_filename = &matches.value_of("OUTPUT").unwrap();
-    let mut out_file = fs::File::create(Path::new(out_filename)).unwrap();
-    let h = wav::Header::new(wav::WAV_FORMAT_PCM, 1, 16000, 16);
-    let out_copy = out.clone();
-    wav::write(h, &wav::BitDepth::Sixteen(out_copy), &mut out_file).unwrap();
+    let opt = opt_ref.borrow().clone();
+    if let Some(path) = &resume {
+        if let Err(e) = state::save(path, &opt, &view_ref.borrow()) {
+            eprintln!("{:#}", e);
+        }
+    } else if let (Leave::Save | Leave::Both, Some(path)) = (leave, &single) {
+        state::save(path, &opt, &view_ref.borrow())?;
+        println!("saved {}", state::sidecar(path).display());
+    }
+    if matches!(leave, Leave::Save | Leave::Discard) {
+        return Ok(());
+    }
+    println!("wrote {}", write_output(&opt, &out)?);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcm_extract::export::{Dither, FileFormat, SampleFormat};
+
+    fn round_trip(opt: &Opts) -> Opts {
+        let args = args_from_opts(opt, None);
+        let argv = ["pcm-extract", "-i", "in", "-o", "out"]
+            .into_iter()
+            .map(String::from)
+            .chain(args);
+        let matches = app().get_matches_from_safe(argv).unwrap();
+        opts_from_matches(&matches, Opts::default()).unwrap()
+    }
+
+    #[test]
+    fn defaults_need_no_flags() {
+        assert!(args_from_opts(&Opts::default(), None).is_empty());
+    }
+
+    #[test]
+    fn args_round_trip_every_flagged_setting() {
+        let opt = Opts {
+            representation: Representation::SignedMagnitude,
+            compression: Compression::DPCM2,
+            stages: vec![Compression::DPCM1],
+            k: 3,
+            flip: 5,
+            mirror: 200,
+            fold: Fold::Clamp,
+            sign: SignBit(3),
+            nibble_swap: true,
+            mask: 0x3F,
+            mask_shift: true,
+            nibble_order: NibbleOrder::Low,
+            creative_bits: 2,
+            sdx_reset: false,
+            roq_shift: 2,
+            dpcm0_gain: Gain::Shift(4),
+            adaptive: "0.5,2,64".parse().unwrap(),
+            frame_size: Some(18),
+            byte_planes: true,
+            plane_boundary: Some(100),
+            on_error: OnError::Stop,
+            partial: Partial::Pad,
+            step: 2,
+            skip: 7,
+            rate: 22050,
+            stereo: true,
+            planar: true,
+            downmix: true,
+            oversample: 2,
+            sample_format: SampleFormat::F32,
+            file_format: FileFormat::Flac,
+            dither: Dither::Tpdf,
+            embed_markers: true,
+            warmup: Some(32),
+            max_samples: Some(1000),
+            windowed: true,
+            settle: true,
+            zcr_threshold: 0.25,
+            highpass: 40.0,
+            gate: 12,
+            bandpass: Some((100.0, 3000.5)),
+            ..Opts::default()
+        };
+        assert_eq!(round_trip(&opt), opt);
+        let mono = Opts {
+            upmix: true,
+            zcr_threshold: 0.05,
+            ..Opts::default()
+        };
+        assert_eq!(round_trip(&mono), mono);
+    }
+}
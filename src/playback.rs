@@ -0,0 +1,346 @@
+use crate::decode::Opts;
+use crate::error::{PcmError, Result};
+use crate::view::ViewOpts;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+struct Meter {
+    peak: i16,
+    sum_sq: f64,
+    count: u32,
+    period: u32,
+}
+
+impl Meter {
+    fn new(period: u32) -> Meter {
+        Meter {
+            peak: 0,
+            sum_sq: 0.0,
+            count: 0,
+            period,
+        }
+    }
+
+    fn feed(&mut self, value: i16) {
+        self.peak = self.peak.max(value.saturating_abs());
+        self.sum_sq += value as f64 * value as f64;
+        self.count += 1;
+        if self.count >= self.period {
+            let rms = (self.sum_sq / self.count as f64).sqrt();
+            let width = self.peak as usize * 40 / i16::MAX as usize;
+            let clip = if self.peak == i16::MAX { " CLIP" } else { "" };
+            eprint!(
+                "\r[{:<40}] peak {:>5} rms {:>7.1}{}",
+                "#".repeat(width),
+                self.peak,
+                rms,
+                clip
+            );
+            std::io::stderr().flush().ok();
+            *self = Meter::new(self.period);
+        }
+    }
+}
+
+// Times are in milliseconds and sustain is a gain between 0 and 1. The
+// release starts so that it ends with the played range.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Envelope {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Envelope {
+    fn gain(&self, ix: usize, len: usize, rate: u32) -> f64 {
+        let ms = |t: usize| t as f64 * 1000.0 / rate as f64;
+        let t = ms(ix);
+        let left = ms(len - ix);
+        let level = if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.decay {
+            1.0 - (1.0 - self.sustain) * (t - self.attack) / self.decay
+        } else {
+            self.sustain
+        };
+        if left < self.release {
+            level.min(self.sustain * left / self.release)
+        } else {
+            level
+        }
+    }
+}
+
+impl std::fmt::Display for Envelope {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.attack, self.decay, self.sustain, self.release
+        )
+    }
+}
+
+impl std::str::FromStr for Envelope {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<Envelope> {
+        let v = s
+            .split(',')
+            .map(|x| x.trim().parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| PcmError::Invalid(format!("{:?}: {}", s, e)))?;
+        match v[..] {
+            [attack, decay, sustain, release] => Ok(Envelope {
+                attack,
+                decay,
+                sustain,
+                release,
+            }),
+            _ => Err(PcmError::Invalid(
+                "expected attack,decay,sustain,release".to_string(),
+            )),
+        }
+    }
+}
+
+// Feedback is kept below this so repeats always die out.
+pub const MAX_FEEDBACK: f64 = 0.9;
+
+// A delay in milliseconds whose output is fed back into itself, attenuated
+// by feedback.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Echo {
+    pub delay: f64,
+    pub feedback: f64,
+}
+
+impl Echo {
+    fn apply(&self, samples: &mut [i16], channels: usize, rate: u32) {
+        let frames = (self.delay * rate as f64 / 1000.0).round() as usize;
+        if frames == 0 {
+            return;
+        }
+        let mut ring = vec![0.0; frames * channels];
+        for (x, delayed) in samples.iter_mut().zip((0..ring.len()).cycle()) {
+            let y = *x as f64 + self.feedback * ring[delayed];
+            ring[delayed] = y;
+            *x = y.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+        }
+    }
+}
+
+impl std::fmt::Display for Echo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{}", self.delay, self.feedback)
+    }
+}
+
+impl std::str::FromStr for Echo {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<Echo> {
+        let v = s
+            .split(',')
+            .map(|x| x.trim().parse::<f64>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| PcmError::Invalid(format!("{:?}: {}", s, e)))?;
+        match v[..] {
+            [delay, feedback] if delay >= 0.0 && (0.0..=MAX_FEEDBACK).contains(&feedback) => {
+                Ok(Echo { delay, feedback })
+            }
+            [_, _] => Err(PcmError::Invalid(format!(
+                "delay must not be negative and feedback must be between 0 and {}",
+                MAX_FEEDBACK
+            ))),
+            _ => Err(PcmError::Invalid("expected delay,feedback".to_string())),
+        }
+    }
+}
+
+// The output device is opened on first use so that machines without audio
+// can still convert and plot. Playback runs in the background; the stream is
+// kept until the next play or stop.
+#[derive(Default)]
+pub struct Player {
+    output: Option<(cpal::Device, cpal::SupportedStreamConfig)>,
+    playing: Option<Playing>,
+}
+
+// Shared with the stream callback; position counts device frames.
+#[derive(Default)]
+struct Cursor {
+    position: AtomicU64,
+    paused: AtomicBool,
+}
+
+struct Playing {
+    _stream: cpal::Stream,
+    cursor: Arc<Cursor>,
+    // sample index of the first played sample, and the played length
+    start: usize,
+    len: usize,
+    channels: usize,
+    rate: u32,
+    device_rate: u32,
+}
+
+impl Player {
+    pub fn play(
+        &mut self,
+        mut samples: Vec<i16>,
+        start: usize,
+        opt: &Opts,
+        view: &ViewOpts,
+    ) -> Result<()> {
+        self.stop();
+        let channels = crate::decode::channels(opt);
+        if let Some(envelope) = view.envelope {
+            let len = samples.len() / channels;
+            for (ix, x) in samples.iter_mut().enumerate() {
+                let gain = envelope.gain((ix / channels).min(len), len, opt.rate);
+                *x = (*x as f64 * gain).round() as i16;
+            }
+        }
+        if let Some(echo) = view.echo {
+            echo.apply(&mut samples, channels, opt.rate);
+        }
+        if self.output.is_none() {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| PcmError::Audio("no output device available".to_string()))?;
+            let config = device.default_output_config().map_err(|e| {
+                PcmError::Audio(format!("failed to get the device configuration: {}", e))
+            })?;
+            self.output = Some((device, config));
+        }
+        let (device, config) = self.output.as_ref().unwrap();
+        let cursor = Arc::new(Cursor::default());
+        let len = samples.len();
+        let stream = play(
+            device,
+            config,
+            samples,
+            opt.rate,
+            channels,
+            view.meter,
+            Arc::clone(&cursor),
+        )?;
+        self.playing = Some(Playing {
+            _stream: stream,
+            cursor,
+            start,
+            len,
+            channels,
+            rate: opt.rate,
+            device_rate: config.sample_rate().0,
+        });
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> bool {
+        self.playing.take().is_some()
+    }
+
+    // The stream keeps running and outputs silence while paused, so resuming
+    // does not have to reopen the device.
+    pub fn pause(&self, paused: bool) -> Result<()> {
+        let p = self
+            .playing
+            .as_ref()
+            .ok_or_else(|| PcmError::Invalid("nothing is playing".to_string()))?;
+        p.cursor.paused.store(paused, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Moves the cursor to a sample index of the decoded stream, which must
+    // lie within what is playing. Seeking after the end restarts output.
+    pub fn seek(&self, index: usize) -> Result<()> {
+        let p = self
+            .playing
+            .as_ref()
+            .ok_or_else(|| PcmError::Invalid("nothing is playing".to_string()))?;
+        if index < p.start || index >= p.start + p.len {
+            return Err(PcmError::Invalid(format!(
+                "{} is outside the played range {}..{}",
+                index,
+                p.start,
+                p.start + p.len
+            )));
+        }
+        let frame = ((index - p.start) / p.channels) as u64;
+        p.cursor.position.store(
+            frame * p.device_rate as u64 / p.rate as u64,
+            Ordering::Relaxed,
+        );
+        Ok(())
+    }
+}
+
+fn play(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    samples: Vec<i16>,
+    rate: u32,
+    channels: usize,
+    meter: bool,
+    cursor: Arc<Cursor>,
+) -> Result<cpal::Stream> {
+    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+
+    let rate = rate as u64;
+
+    let mut done = false;
+    let sc: cpal::StreamConfig = config.clone().into();
+    // about ten meter updates per second
+    let mut levels = Meter::new(sc.sample_rate.0 / 10);
+    let stream = device
+        .build_output_stream(
+            &sc,
+            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                if cursor.paused.load(Ordering::Relaxed) {
+                    data.iter_mut().for_each(|s| *s = 0);
+                    return;
+                }
+                let first = cursor.position.load(Ordering::Relaxed);
+                let mut frames = first;
+                for frame in data.chunks_mut(sc.channels as usize) {
+                    // resample to the device rate
+                    let ix = (frames * rate / sc.sample_rate.0 as u64) as usize * channels;
+                    if ix + channels <= samples.len() {
+                        done = false;
+                        if meter {
+                            levels.feed(samples[ix]);
+                        }
+                        // a mono device only gets the left channel
+                        for (c, sample) in frame.iter_mut().enumerate() {
+                            *sample = cpal::Sample::from::<i16>(&samples[ix + c.min(channels - 1)]);
+                        }
+                        frames += 1;
+                    } else {
+                        if !done && meter {
+                            eprintln!();
+                        }
+                        done = true;
+                        frame.iter_mut().for_each(|s| *s = 0);
+                    }
+                }
+                // a seek from the REPL while this buffer was filled wins
+                let _ = cursor.position.compare_exchange(
+                    first,
+                    frames,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+            },
+            err_fn,
+        )
+        .map_err(|e| PcmError::Audio(format!("failed to open a stream: {}", e)))?;
+    stream
+        .play()
+        .map_err(|e| PcmError::Audio(format!("failed to start the stream: {}", e)))?;
+    Ok(stream)
+}
@@ -0,0 +1,269 @@
+use crate::decode::{self, Opts};
+use crate::view::ViewOpts;
+use textplots::{Chart, Plot, Shape};
+
+fn scale(y: f32, view: &ViewOpts) -> f32 {
+    if view.logplot {
+        y.signum() * y.abs().ln_1p()
+    } else {
+        y
+    }
+}
+
+// The fixed vertical axis, in plotted units, unless autoscaling.
+fn y_range(view: &ViewOpts) -> Option<(f32, f32)> {
+    if view.autoscale {
+        None
+    } else {
+        Some((
+            scale(view.yrange.0 as f32, view),
+            scale(view.yrange.1 as f32, view),
+        ))
+    }
+}
+
+// Values past a fixed axis are pinned to its edge rather than dropped.
+fn amplitude(y: f32, view: &ViewOpts) -> f32 {
+    match y_range(view) {
+        Some((min, max)) => scale(y, view).clamp(min, max),
+        None => scale(y, view),
+    }
+}
+
+fn chart(width: u32, height: u32, xmin: f32, xmax: f32, y: Option<(f32, f32)>) -> Chart<'static> {
+    match y {
+        Some((ymin, ymax)) => Chart::new_with_y_range(width, height, xmin, xmax, ymin, ymax),
+        None => Chart::new(width, height, xmin, xmax),
+    }
+}
+
+// Chart sizes are in Braille dots, two per column and four per row.
+fn size(view: &ViewOpts) -> (u32, u32) {
+    if view.hires {
+        (600, 160)
+    } else {
+        (300, 60)
+    }
+}
+
+fn display(opt: &Opts, view: &ViewOpts, y: Option<(f32, f32)>, shapes: &[Shape]) {
+    let (width, height) = size(view);
+    let mut chart = chart(width, height, opt.from as f32, opt.to as f32, y);
+    let mut c = &mut chart;
+    for shape in shapes {
+        c = c.lineplot(shape);
+    }
+    c.display();
+}
+
+// The x-axis counts samples of the whole decoded stream, not of the window.
+fn axis_caption(opt: &Opts) -> String {
+    let seconds = |i: usize| i as f64 / opt.rate as f64;
+    format!(
+        "x: sample {}..{} ({:.3}s..{:.3}s at {} Hz)",
+        opt.from,
+        opt.to,
+        seconds(opt.from),
+        seconds(opt.to),
+        opt.rate
+    )
+}
+
+// A vertical line across the plotted samples at the cursor, when it is in
+// the view.
+fn cursor_line(plt: &[(f32, f32)], opt: &Opts, view: &ViewOpts) -> Vec<(f32, f32)> {
+    let index = match view.cursor {
+        Some(index) if (opt.from..opt.to).contains(&index) => index as f32,
+        _ => return vec![],
+    };
+    let (min, max) = y_range(view).unwrap_or_else(|| {
+        plt.iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &(_, y)| {
+                (min.min(y), max.max(y))
+            })
+    });
+    if min > max {
+        return vec![];
+    }
+    vec![(index, min), (index, max)]
+}
+
+// `out` holds the decoded stream from sample `origin` on.
+pub fn draw(out: &[i16], origin: usize, input: &[u8], opt: &Opts, view: &ViewOpts) {
+    let mut plt = vec![];
+    let mut right = vec![];
+    let mut clipped = vec![];
+    let end = opt.to.min(origin + out.len());
+    for (i, x) in (origin..).zip(out).take_while(|&(i, _)| i < end) {
+        if i < opt.from {
+            continue;
+        }
+        if opt.stereo && i % 2 == 1 {
+            right.push((i as f32, amplitude(*x as f32, view)));
+        } else {
+            plt.push((i as f32, amplitude(*x as f32, view)));
+        }
+        if view.clip_marks && (*x == i16::MIN || *x == i16::MAX) {
+            clipped.push((i as f32, amplitude(*x as f32, view)));
+        }
+    }
+    let mut plt2 = vec![];
+    // each sample is paired with the byte it was decoded from
+    for i in opt.from..end {
+        let x = match input.get(decode::input_index(i, input.len(), opt)) {
+            Some(x) => x,
+            None => break,
+        };
+        if view.overlay {
+            // bytes are centered and scaled to the 16-bit range of the output
+            plt2.push((i as f32, amplitude((*x as f32 - 128.0) * 256.0, view)));
+        } else if view.center_input {
+            plt2.push((i as f32, *x as f32 - 128.0));
+        } else {
+            plt2.push((i as f32, *x as f32));
+        }
+    }
+    let cursor = cursor_line(&plt, opt, view);
+    let mut shapes = vec![Shape::Steps(&plt)];
+    if !clipped.is_empty() {
+        shapes.push(Shape::Points(&clipped));
+    }
+    if !cursor.is_empty() {
+        shapes.push(Shape::Lines(&cursor));
+    }
+    if view.overlay {
+        if opt.stereo {
+            shapes.push(Shape::Steps(&right));
+        }
+        shapes.push(Shape::Points(&plt2));
+        display(opt, view, y_range(view), &shapes);
+    } else {
+        display(opt, view, y_range(view), &shapes);
+        if opt.stereo {
+            // left above, right below; both keep their interleaved index
+            display(opt, view, y_range(view), &[Shape::Steps(&right)]);
+        }
+        display(opt, view, None, &[Shape::Steps(&plt2)]);
+    }
+    println!("{}", axis_caption(opt));
+}
+
+pub fn draw_spectrum(mags: &[f64], opt: &Opts, view: &ViewOpts) {
+    let rate = opt.rate;
+    let (width, height) = size(view);
+    let bin = rate as f32 / (2 * mags.len()) as f32;
+    let plt: Vec<(f32, f32)> = mags
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (i as f32 * bin, *m as f32))
+        .collect();
+    Chart::new(width, height, 0.0, rate as f32 / 2.0)
+        .lineplot(&Shape::Lines(&plt))
+        .display();
+}
+
+// Min and max of each of `columns` equal slices of the samples.
+fn min_max(out: &[i16], columns: usize) -> Vec<(usize, i16, i16)> {
+    let per = out.len().div_ceil(columns).max(1);
+    out.chunks(per)
+        .enumerate()
+        .map(|(i, c)| {
+            let (min, max) = c.iter().fold((i16::MAX, i16::MIN), |(min, max), &x| {
+                (min.min(x), max.max(x))
+            });
+            (i * per, min, max)
+        })
+        .collect()
+}
+
+// The whole decoded stream as a min/max envelope, one slice per dot
+// column, with the current view between two vertical lines.
+pub fn draw_overview(out: &[i16], opt: &Opts, view: &ViewOpts) {
+    let (width, height) = size(view);
+    let columns = min_max(out, width as usize);
+    let max: Vec<(f32, f32)> = columns
+        .iter()
+        .map(|&(i, _, max)| (i as f32, amplitude(max as f32, view)))
+        .collect();
+    let min: Vec<(f32, f32)> = columns
+        .iter()
+        .map(|&(i, min, _)| (i as f32, amplitude(min as f32, view)))
+        .collect();
+    let (low, high) = y_range(view).unwrap_or_else(|| {
+        min.iter()
+            .chain(&max)
+            .fold((f32::MAX, f32::MIN), |(low, high), &(_, y)| {
+                (low.min(y), high.max(y))
+            })
+    });
+    let bounds: Vec<Vec<(f32, f32)>> = [opt.from, opt.to.min(out.len())]
+        .iter()
+        .map(|&i| vec![(i as f32, low), (i as f32, high)])
+        .collect();
+    let mut shapes = vec![Shape::Steps(&max), Shape::Steps(&min)];
+    if low <= high {
+        shapes.extend(bounds.iter().map(|v| Shape::Lines(v)));
+    }
+    let mut chart = chart(width, height, 0.0, out.len().max(1) as f32, y_range(view));
+    let mut c = &mut chart;
+    for shape in &shapes {
+        c = c.lineplot(shape);
+    }
+    c.display();
+    println!(
+        "overview: {} samples ({:.3}s), {} per column, view {}..{}",
+        out.len(),
+        out.len() as f64 / opt.rate as f64,
+        out.len().div_ceil(width as usize).max(1),
+        opt.from,
+        opt.to
+    );
+}
+
+const GRID_COLUMNS: usize = 2;
+
+// Renders each (start, samples) window as a small chart, laid out in rows of
+// GRID_COLUMNS tiles.
+pub fn draw_grid(tiles: &[(usize, &[i16])], width: usize, view: &ViewOpts) {
+    let rendered: Vec<Vec<String>> = tiles
+        .iter()
+        .map(|(start, samples)| {
+            let plt: Vec<(f32, f32)> = samples
+                .iter()
+                .enumerate()
+                .map(|(i, x)| ((start + i) as f32, amplitude(*x as f32, view)))
+                .collect();
+            let text = chart(
+                120,
+                32,
+                *start as f32,
+                (start + width) as f32,
+                y_range(view),
+            )
+            .lineplot(&Shape::Steps(&plt))
+            .to_string();
+            let mut lines = vec![format!("{}..{}", start, start + width)];
+            lines.extend(text.lines().map(String::from));
+            lines
+        })
+        .collect();
+    for row in rendered.chunks(GRID_COLUMNS) {
+        let pad = row
+            .iter()
+            .flatten()
+            .map(|l| l.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let height = row.iter().map(|t| t.len()).max().unwrap_or(0);
+        for i in 0..height {
+            let mut line = String::new();
+            for tile in row {
+                let cell = tile.get(i).map(String::as_str).unwrap_or("");
+                line.push_str(cell);
+                line.extend(std::iter::repeat_n(' ', pad - cell.chars().count()));
+            }
+            println!("{}", line.trim_end());
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use easy_repl::command::ArgsError;
+use easy_repl::{Command, CommandStatus, CriticalError};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Context, Editor, Helper, OutputStreamType};
+use std::collections::BTreeMap;
+
+// The prompt loop around easy-repl's commands. easy-repl's own Repl looks
+// names up by prefix only, so a command whose name starts another one (range
+// and ranges, log and logplot) could never be run; here a name typed in full
+// always runs that command and any other unambiguous prefix still picks the
+// command it starts.
+
+const HELP_WIDTH: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopStatus {
+    Continue,
+    Break,
+}
+
+#[derive(Default)]
+pub struct Builder<'a> {
+    commands: Vec<(String, Command<'a>)>,
+}
+
+impl<'a> Builder<'a> {
+    pub fn add(mut self, name: &str, cmd: Command<'a>) -> Self {
+        self.commands.push((name.to_string(), cmd));
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Repl<'a>> {
+        let mut commands = BTreeMap::new();
+        for (name, cmd) in self.commands {
+            if name.is_empty() || name.contains(char::is_whitespace) || name == "help" {
+                anyhow::bail!("invalid command name {:?}", name);
+            }
+            if commands.insert(name.clone(), cmd).is_some() {
+                anyhow::bail!("command {:?} is added twice", name);
+            }
+        }
+        let config = rustyline::Config::builder()
+            .output_stream(OutputStreamType::Stderr)
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config);
+        editor.set_helper(Some(Names(
+            commands
+                .keys()
+                .cloned()
+                .chain(["help".to_string()])
+                .collect(),
+        )));
+        Ok(Repl { commands, editor })
+    }
+}
+
+pub struct Repl<'a> {
+    commands: BTreeMap<String, Command<'a>>,
+    editor: Editor<Names>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn builder() -> Builder<'a> {
+        Builder::default()
+    }
+
+    // Reads and runs one line. Ctrl-C, the end of input and commands
+    // returning Quit all break.
+    pub fn next(&mut self) -> anyhow::Result<LoopStatus> {
+        match self.editor.readline("> ") {
+            Ok(line) if line.trim().is_empty() => Ok(LoopStatus::Continue),
+            Ok(line) => {
+                self.editor.add_history_entry(line.trim());
+                self.run(&line)
+            }
+            Err(ReadlineError::Interrupted) => {
+                eprintln!("CTRL-C");
+                Ok(LoopStatus::Break)
+            }
+            Err(ReadlineError::Eof) => Ok(LoopStatus::Break),
+            Err(err) => {
+                eprintln!("Error: {:?}", err);
+                Ok(LoopStatus::Continue)
+            }
+        }
+    }
+
+    fn run(&mut self, line: &str) -> anyhow::Result<LoopStatus> {
+        let args = match shell_words::split(line) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("Error: {:#}", err);
+                return Ok(LoopStatus::Continue);
+            }
+        };
+        let name = match self.find(&args[0]) {
+            Ok(name) => name,
+            Err(candidates) => {
+                eprintln!("Command not found: {}", args[0]);
+                if candidates.len() > 1 {
+                    eprintln!("Candidates:\n  {}", candidates.join("\n  "));
+                }
+                eprintln!("Use 'help' to see available commands.");
+                return Ok(LoopStatus::Continue);
+            }
+        };
+        if name == "help" {
+            eprintln!("{}", self.help());
+            return Ok(LoopStatus::Continue);
+        }
+        let tail: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+        let cmd = self.commands.get_mut(&name).unwrap();
+        match cmd.run(&tail) {
+            Ok(CommandStatus::Done) => Ok(LoopStatus::Continue),
+            Ok(CommandStatus::Quit) => Ok(LoopStatus::Break),
+            Err(err) if err.downcast_ref::<CriticalError>().is_some() => Err(err),
+            Err(err) => {
+                eprintln!("Error: {:#}", err);
+                if err.downcast_ref::<ArgsError>().is_some() {
+                    eprintln!("Usage: {} {}", name, cmd.args_info.join(" "));
+                }
+                Ok(LoopStatus::Continue)
+            }
+        }
+    }
+
+    // The command a typed name runs, or the names it could have meant.
+    fn find(&self, typed: &str) -> Result<String, Vec<String>> {
+        let names = &self.editor.helper().unwrap().0;
+        if names.iter().any(|n| n == typed) {
+            return Ok(typed.to_string());
+        }
+        match starting_with(names, typed)[..] {
+            [name] => Ok(name.to_string()),
+            ref candidates => Err(candidates.iter().map(|n| n.to_string()).collect()),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut entries: Vec<(String, &str)> = self
+            .commands
+            .iter()
+            .map(|(name, cmd)| {
+                let signature = format!("{} {}", name, cmd.args_info.join(" "));
+                (signature.trim_end().to_string(), cmd.description.as_str())
+            })
+            .collect();
+        entries.push(("help".to_string(), "Show this help message"));
+        // a few signatures list every variant, so those get a line of their
+        // own rather than pushing every description across the screen
+        let width = entries
+            .iter()
+            .map(|(s, _)| s.len())
+            .filter(|&len| len <= HELP_WIDTH)
+            .max()
+            .unwrap_or(0);
+        let lines: Vec<String> = entries
+            .iter()
+            .map(|(signature, description)| {
+                if signature.len() > width {
+                    format!(
+                        "  {}\n  {:width$}  {}",
+                        signature,
+                        "",
+                        description,
+                        width = width
+                    )
+                } else {
+                    format!("  {:width$}  {}", signature, description, width = width)
+                }
+            })
+            .collect();
+        format!("Available commands:\n{}", lines.join("\n"))
+    }
+}
+
+fn starting_with<'n>(names: &'n [String], prefix: &str) -> Vec<&'n str> {
+    names
+        .iter()
+        .filter(|n| n.starts_with(prefix))
+        .map(String::as_str)
+        .collect()
+}
+
+// Command names for completing and hinting the first word of a line.
+struct Names(Vec<String>);
+
+impl Completer for Names {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line.len() - line.trim_start().len();
+        if pos < start || line[start..pos].contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        let candidates = starting_with(&self.0, &line[start..pos])
+            .into_iter()
+            .map(|n| Pair {
+                display: n.to_string(),
+                replacement: format!("{} ", n),
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Names {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        let word = line.trim_start();
+        if pos < line.len() || word.is_empty() || word.contains(char::is_whitespace) {
+            return None;
+        }
+        match starting_with(&self.0, word)[..] {
+            [name] => Some(name[word.len()..].to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for Names {}
+
+impl Validator for Names {}
+
+impl Helper for Names {}
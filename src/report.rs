@@ -0,0 +1,101 @@
+use crate::analysis;
+use crate::block::Marker;
+use crate::decode::Opts;
+use std::fmt::Write;
+
+pub struct Report<'a> {
+    pub inputs: &'a [std::path::PathBuf],
+    pub input_len: usize,
+    pub command: String,
+    pub opt: &'a Opts,
+    pub decoded_len: usize,
+    pub window: &'a [i16],
+    pub markers: Vec<Marker>,
+    pub ranges: &'a [(usize, usize)],
+}
+
+// A plain text summary of one file under the current settings, collecting
+// what stats, detect-rate, marks and ranges print.
+pub fn text(r: &Report) -> String {
+    let opt = r.opt;
+    let seconds = |i: usize| i as f64 / opt.rate as f64;
+    let mut s = String::new();
+    // writing to a String cannot fail
+    let mut line = |text: String| writeln!(s, "{}", text).unwrap();
+
+    for input in r.inputs {
+        line(format!("input: {}", input.display()));
+    }
+    line(format!("size: {} bytes", r.input_len));
+    line(format!("representation: {}", opt.representation));
+    line(format!("compression: {}", opt.compression));
+    if !opt.stages.is_empty() {
+        let stages: Vec<String> = opt.stages.iter().map(|c| c.to_string()).collect();
+        line(format!("stages: {}", stages.join(", ")));
+    }
+    line(format!(
+        "decoded: {} samples, {:.3}s at {} Hz",
+        r.decoded_len,
+        seconds(r.decoded_len),
+        opt.rate
+    ));
+    line(format!("command: {}", r.command));
+
+    line(String::new());
+    line(format!(
+        "range: {}..{} ({:.3}s..{:.3}s)",
+        opt.from,
+        opt.to,
+        seconds(opt.from),
+        seconds(opt.to)
+    ));
+    let st = analysis::stats(r.window);
+    line(format!(
+        "rms: {:.1} ({:.1} dBFS)",
+        st.rms,
+        analysis::dbfs(st.rms)
+    ));
+    line(format!(
+        "peak: {} ({:.1} dBFS)",
+        st.peak,
+        analysis::dbfs(st.peak as f64)
+    ));
+    line(format!("dc: {:.1}", st.dc));
+    line(format!(
+        "zcr: {:.4} ({})",
+        st.zcr,
+        analysis::classify(&st, opt.zcr_threshold)
+    ));
+    match analysis::pitch_period(r.window) {
+        None => line("pitch: no clear period".into()),
+        Some(period) => {
+            let freq = opt.rate as f64 / period;
+            let (note, _, cents) = analysis::nearest_note(freq);
+            line(format!(
+                "pitch: period {:.2} samples, {:.1} Hz at {} Hz ({} {:+.0} cents)",
+                period, freq, opt.rate, note, cents
+            ));
+        }
+    }
+
+    if !r.markers.is_empty() {
+        line(String::new());
+        line("markers:".into());
+        for m in &r.markers {
+            line(format!(
+                "  {:?} at {} ({:.3}s)",
+                m.kind,
+                m.index,
+                seconds(m.index)
+            ));
+        }
+    }
+    if !r.ranges.is_empty() {
+        line(String::new());
+        line("ranges:".into());
+        for (i, (from, to)) in r.ranges.iter().enumerate() {
+            line(format!("  {}: {}..{} ({} samples)", i, from, to, to - from));
+        }
+    }
+    s
+}
@@ -0,0 +1,356 @@
+use crate::decode::Opts;
+use crate::error::{PcmError, Result};
+use crate::toc::TocFormat;
+use crate::view::ViewOpts;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Settings are kept next to the input as key=value lines.
+pub fn sidecar(input: &Path) -> PathBuf {
+    let mut name = input.as_os_str().to_owned();
+    name.push(".pcm-extract");
+    PathBuf::from(name)
+}
+
+pub fn to_text(opt: &Opts, view: &ViewOpts) -> String {
+    let stages: Vec<String> = opt.stages.iter().map(|c| c.to_string()).collect();
+    let table: Vec<String> = opt.table.iter().map(|d| d.to_string()).collect();
+    let fields = [
+        ("from", opt.from.to_string()),
+        ("to", opt.to.to_string()),
+        ("min_width", view.min_width.to_string()),
+        (
+            "cursor",
+            view.cursor.map_or("off".to_string(), |c| c.to_string()),
+        ),
+        ("step", opt.step.to_string()),
+        ("skip", opt.skip.to_string()),
+        ("rate", opt.rate.to_string()),
+        ("highpass", opt.highpass.to_string()),
+        (
+            "bandpass",
+            opt.bandpass
+                .map_or("off".to_string(), |(low, high)| format!("{},{}", low, high)),
+        ),
+        ("gate", opt.gate.to_string()),
+        ("zcr_threshold", opt.zcr_threshold.to_string()),
+        ("logplot", view.logplot.to_string()),
+        ("hires", view.hires.to_string()),
+        ("autoscale", view.autoscale.to_string()),
+        ("yrange", format!("{},{}", view.yrange.0, view.yrange.1)),
+        ("overlay", view.overlay.to_string()),
+        ("center_input", view.center_input.to_string()),
+        ("prompt_length", view.prompt_length.to_string()),
+        ("clip_marks", view.clip_marks.to_string()),
+        ("window", view.window.to_string()),
+        ("meter", view.meter.to_string()),
+        (
+            "envelope",
+            view.envelope.map_or("off".to_string(), |e| e.to_string()),
+        ),
+        (
+            "echo",
+            view.echo.map_or("off".to_string(), |e| e.to_string()),
+        ),
+        ("windowed", opt.windowed.to_string()),
+        (
+            "warmup",
+            opt.warmup.map_or("auto".to_string(), |w| w.to_string()),
+        ),
+        (
+            "max_samples",
+            opt.max_samples.map_or("off".to_string(), |m| m.to_string()),
+        ),
+        ("settle", opt.settle.to_string()),
+        ("k", opt.k.to_string()),
+        ("flip", opt.flip.to_string()),
+        ("mirror", opt.mirror.to_string()),
+        ("fold", opt.fold.to_string()),
+        ("sign", opt.sign.to_string()),
+        ("nibble_swap", opt.nibble_swap.to_string()),
+        ("mask", format!("{:02x}", opt.mask)),
+        ("mask_shift", opt.mask_shift.to_string()),
+        ("nibble_order", opt.nibble_order.to_string()),
+        ("creative_bits", opt.creative_bits.to_string()),
+        ("sdx_reset", opt.sdx_reset.to_string()),
+        ("roq_shift", opt.roq_shift.to_string()),
+        ("dpcm0_gain", opt.dpcm0_gain.to_string()),
+        ("adaptive", opt.adaptive.to_string()),
+        (
+            "frame_size",
+            opt.frame_size
+                .map_or("native".to_string(), |s| s.to_string()),
+        ),
+        ("byte_planes", opt.byte_planes.to_string()),
+        (
+            "plane_boundary",
+            opt.plane_boundary
+                .map_or("mid".to_string(), |b| b.to_string()),
+        ),
+        ("on_error", opt.on_error.to_string()),
+        ("partial", opt.partial.to_string()),
+        ("representation", opt.representation.to_string()),
+        ("compression", opt.compression.to_string()),
+        ("stages", stages.join(",")),
+        ("table", table.join(",")),
+        ("stereo", opt.stereo.to_string()),
+        ("planar", opt.planar.to_string()),
+        ("downmix", opt.downmix.to_string()),
+        ("upmix", opt.upmix.to_string()),
+        ("oversample", opt.oversample.to_string()),
+        ("sample_format", opt.sample_format.to_string()),
+        ("file_format", opt.file_format.to_string()),
+        ("dither", opt.dither.to_string()),
+        ("embed_markers", opt.embed_markers.to_string()),
+    ];
+    fields
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T>
+where
+    T::Err: fmt::Display,
+{
+    value.parse().map_err(|e| invalid(format!("{}", e)))
+}
+
+fn invalid(what: impl Into<String>) -> PcmError {
+    PcmError::Invalid(what.into())
+}
+
+pub fn from_text(text: &str, opt: &mut Opts, view: &mut ViewOpts) -> Result<()> {
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (key, value) = match line.split_once('=') {
+            Some(kv) => kv,
+            None => return Err(invalid(format!("expected key=value, got {:?}", line))),
+        };
+        let parsed: Result<()> = (|| {
+            match key {
+                "from" => opt.from = parse(value)?,
+                "to" => opt.to = parse(value)?,
+                "min_width" => view.min_width = parse(value)?,
+                "cursor" if value == "off" => view.cursor = None,
+                "cursor" => view.cursor = Some(parse(value)?),
+                "step" => opt.step = crate::decode::parse_step(value)?,
+                "skip" => opt.skip = parse(value)?,
+                "rate" => opt.rate = parse(value)?,
+                "highpass" => opt.highpass = parse(value)?,
+                "bandpass" if value == "off" => opt.bandpass = None,
+                "bandpass" => {
+                    let (low, high) = value
+                        .split_once(',')
+                        .ok_or_else(|| invalid("expected low,high"))?;
+                    opt.bandpass = Some((parse(low)?, parse(high)?));
+                }
+                "gate" => opt.gate = parse(value)?,
+                "zcr_threshold" => opt.zcr_threshold = parse(value)?,
+                "logplot" => view.logplot = parse(value)?,
+                "hires" => view.hires = parse(value)?,
+                "autoscale" => view.autoscale = parse(value)?,
+                "yrange" => {
+                    let (min, max) = value
+                        .split_once(',')
+                        .ok_or_else(|| invalid("expected min,max"))?;
+                    view.yrange = (parse(min)?, parse(max)?);
+                }
+                "overlay" => view.overlay = parse(value)?,
+                "center_input" => view.center_input = parse(value)?,
+                "prompt_length" => view.prompt_length = parse(value)?,
+                "clip_marks" => view.clip_marks = parse(value)?,
+                "window" => view.window = parse(value)?,
+                "meter" => view.meter = parse(value)?,
+                "envelope" if value == "off" => view.envelope = None,
+                "envelope" => view.envelope = Some(parse(value)?),
+                "echo" if value == "off" => view.echo = None,
+                "echo" => view.echo = Some(parse(value)?),
+                "windowed" => opt.windowed = parse(value)?,
+                "warmup" if value == "auto" => opt.warmup = None,
+                "warmup" => opt.warmup = Some(parse(value)?),
+                "max_samples" if value == "off" => opt.max_samples = None,
+                "max_samples" => opt.max_samples = Some(parse(value)?),
+                "settle" => opt.settle = parse(value)?,
+                "k" => opt.k = parse(value)?,
+                "flip" => opt.flip = parse(value)?,
+                "mirror" => opt.mirror = parse(value)?,
+                "fold" => opt.fold = parse(value)?,
+                "sign" => opt.sign = parse(value)?,
+                "nibble_swap" => opt.nibble_swap = parse(value)?,
+                "mask" => opt.mask = crate::decode::parse_hex(value)?,
+                "mask_shift" => opt.mask_shift = parse(value)?,
+                "nibble_order" => opt.nibble_order = parse(value)?,
+                "creative_bits" => opt.creative_bits = parse(value)?,
+                "sdx_reset" => opt.sdx_reset = parse(value)?,
+                "roq_shift" => opt.roq_shift = parse(value)?,
+                "dpcm0_gain" => opt.dpcm0_gain = parse(value)?,
+                "adaptive" => opt.adaptive = parse(value)?,
+                "frame_size" if value == "native" => opt.frame_size = None,
+                "frame_size" => opt.frame_size = Some(parse(value)?),
+                "byte_planes" => opt.byte_planes = parse(value)?,
+                "plane_boundary" if value == "mid" => opt.plane_boundary = None,
+                "plane_boundary" => opt.plane_boundary = Some(parse(value)?),
+                "on_error" => opt.on_error = parse(value)?,
+                "partial" => opt.partial = parse(value)?,
+                "representation" => opt.representation = parse(value)?,
+                "compression" => opt.compression = parse(value)?,
+                "stages" => {
+                    opt.stages = value
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(parse)
+                        .collect::<Result<_>>()?
+                }
+                "table" => {
+                    opt.table = value
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(parse)
+                        .collect::<Result<_>>()?
+                }
+                "stereo" => opt.stereo = parse(value)?,
+                "planar" => opt.planar = parse(value)?,
+                "downmix" => opt.downmix = parse(value)?,
+                "upmix" => opt.upmix = parse(value)?,
+                "oversample" => opt.oversample = parse(value)?,
+                "sample_format" => opt.sample_format = parse(value)?,
+                "file_format" => opt.file_format = parse(value)?,
+                "dither" => opt.dither = parse(value)?,
+                "embed_markers" => opt.embed_markers = parse(value)?,
+                _ => return Err(invalid("unknown setting")),
+            }
+            Ok(())
+        })();
+        parsed.map_err(|e| invalid(format!("bad setting {:?}: {}", line, e)))?;
+    }
+    if opt.from >= opt.to {
+        return Err(invalid(format!(
+            "from {} must be less than to {}",
+            opt.from, opt.to
+        )));
+    }
+    Ok(())
+}
+
+pub fn load(input: &Path, opt: &mut Opts, view: &mut ViewOpts) -> Result<bool> {
+    let path = sidecar(input);
+    if !path.exists() {
+        return Ok(false);
+    }
+    let text = fs::read_to_string(&path)
+        .map_err(PcmError::io(format!("failed to read {}", path.display())))?;
+    from_text(&text, opt, view).map_err(|e| invalid(format!("in {}: {}", path.display(), e)))?;
+    Ok(true)
+}
+
+pub fn save(input: &Path, opt: &Opts, view: &ViewOpts) -> Result<()> {
+    let path = sidecar(input);
+    fs::write(&path, to_text(opt, view))
+        .map_err(PcmError::io(format!("failed to write {}", path.display())))
+}
+
+// Bumped whenever a project file changes in a way older versions would
+// misread; files from newer versions are refused rather than half loaded.
+pub const PROJECT_VERSION: u32 = 1;
+
+// A whole working session: the inputs with their settings, labelled sections,
+// selected ranges and table of contents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Project {
+    pub inputs: Vec<PathBuf>,
+    pub opt: Opts,
+    pub view: ViewOpts,
+    pub sections: Vec<(String, usize, usize)>,
+    pub ranges: Vec<(usize, usize)>,
+    pub toc: Option<TocFormat>,
+}
+
+// The settings lines of the sidecar after a version line and one line per
+// input, section, range and table of contents, so the file stays easy to edit.
+pub fn project_text(project: &Project) -> String {
+    let mut text = format!("version={}\n", PROJECT_VERSION);
+    for input in &project.inputs {
+        text.push_str(&format!("input={}\n", input.display()));
+    }
+    for (name, start, len) in &project.sections {
+        text.push_str(&format!("section={},{},{}\n", name, start, len));
+    }
+    for (from, to) in &project.ranges {
+        text.push_str(&format!("range={},{}\n", from, to));
+    }
+    if let Some(toc) = &project.toc {
+        text.push_str(&format!("toc={}\n", toc));
+    }
+    text.push_str(&to_text(&project.opt, &project.view));
+    text
+}
+
+pub fn parse_project(text: &str) -> Result<Project> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    let version = match lines.next().and_then(|l| l.strip_prefix("version=")) {
+        Some(v) => v
+            .parse::<u32>()
+            .map_err(|e| invalid(format!("bad version {:?}: {}", v, e)))?,
+        None => return Err(invalid("not a project file, expected a version line first")),
+    };
+    if version > PROJECT_VERSION {
+        return Err(invalid(format!(
+            "project version {} is newer than this program supports ({})",
+            version, PROJECT_VERSION
+        )));
+    }
+    let mut project = Project::default();
+    let mut settings = String::new();
+    for line in lines {
+        let parsed: Result<()> = (|| {
+            match line.split_once('=') {
+                Some(("input", path)) => project.inputs.push(PathBuf::from(path)),
+                Some(("section", value)) => {
+                    // the name comes first and may contain commas itself
+                    let mut parts = value.rsplitn(3, ',');
+                    let len = parse(parts.next().unwrap_or(""))?;
+                    let start = parse(
+                        parts
+                            .next()
+                            .ok_or_else(|| invalid("expected name,start,len"))?,
+                    )?;
+                    let name = parts
+                        .next()
+                        .ok_or_else(|| invalid("expected name,start,len"))?;
+                    project.sections.push((name.to_string(), start, len));
+                }
+                Some(("range", value)) => {
+                    let (from, to) = value
+                        .split_once(',')
+                        .ok_or_else(|| invalid("expected from,to"))?;
+                    let (from, to) = (parse(from)?, parse(to)?);
+                    if from >= to {
+                        return Err(invalid("from must be less than to"));
+                    }
+                    project.ranges.push((from, to));
+                }
+                Some(("toc", value)) => project.toc = Some(parse(value)?),
+                _ => {
+                    settings.push_str(line);
+                    settings.push('\n');
+                }
+            }
+            Ok(())
+        })();
+        parsed.map_err(|e| invalid(format!("bad line {:?}: {}", line, e)))?;
+    }
+    from_text(&settings, &mut project.opt, &mut project.view)?;
+    Ok(project)
+}
+
+pub fn load_project(path: &Path) -> Result<Project> {
+    let text = fs::read_to_string(path)
+        .map_err(PcmError::io(format!("failed to read {}", path.display())))?;
+    parse_project(&text).map_err(|e| invalid(format!("in {}: {}", path.display(), e)))
+}
+
+pub fn save_project(path: &Path, project: &Project) -> Result<()> {
+    fs::write(path, project_text(project))
+        .map_err(PcmError::io(format!("failed to write {}", path.display())))
+}
@@ -0,0 +1,168 @@
+use crate::error::{PcmError, Result};
+
+// A table of contents at a fixed place in the input: `entries` pairs of
+// offset and length, each a `field`, with offsets from the start of the
+// input.
+
+#[derive(parse_display::Display, parse_display::FromStr, Debug, Copy, Clone, PartialEq)]
+#[display(style = "lowercase")]
+pub enum Field {
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+}
+
+impl Field {
+    fn width(self) -> usize {
+        match self {
+            Field::U16Le | Field::U16Be => 2,
+            Field::U32Le | Field::U32Be => 4,
+        }
+    }
+
+    fn read(self, b: &[u8]) -> usize {
+        match self {
+            Field::U16Le => u16::from_le_bytes([b[0], b[1]]) as usize,
+            Field::U16Be => u16::from_be_bytes([b[0], b[1]]) as usize,
+            Field::U32Le => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize,
+            Field::U32Be => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TocFormat {
+    pub offset: usize,
+    pub entries: usize,
+    pub field: Field,
+}
+
+impl std::fmt::Display for TocFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{},{}", self.offset, self.entries, self.field)
+    }
+}
+
+fn parse_number(s: &str) -> Option<usize> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// offset,entries with an optional field type, u32le by default.
+impl std::str::FromStr for TocFormat {
+    type Err = PcmError;
+
+    fn from_str(s: &str) -> Result<TocFormat> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        let (offset, entries, field) = match parts[..] {
+            [offset, entries] => (offset, entries, Some(Field::U32Le)),
+            [offset, entries, field] => (offset, entries, field.parse().ok()),
+            _ => {
+                return Err(PcmError::Invalid(
+                    "expected offset,entries[,field]".to_string(),
+                ))
+            }
+        };
+        match (parse_number(offset), parse_number(entries), field) {
+            (Some(offset), Some(entries), Some(field)) => Ok(TocFormat {
+                offset,
+                entries,
+                field,
+            }),
+            _ => Err(PcmError::Invalid(format!(
+                "{:?}: expected numbers and one of u16le, u16be, u32le, u32be",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Track {
+    pub offset: usize,
+    pub len: usize,
+}
+
+pub fn read(data: &[u8], format: &TocFormat) -> Result<Vec<Track>> {
+    let width = format.field.width();
+    let end = format
+        .entries
+        .checked_mul(2 * width)
+        .and_then(|len| len.checked_add(format.offset))
+        .ok_or_else(|| {
+            PcmError::Invalid(format!(
+                "a table of {} entries at byte {} does not fit in memory",
+                format.entries, format.offset
+            ))
+        })?;
+    let table = data.get(format.offset..end).ok_or_else(|| {
+        PcmError::Invalid(format!(
+            "the table of contents ends at byte {}, past the {} byte input",
+            end,
+            data.len()
+        ))
+    })?;
+    table
+        .chunks_exact(2 * width)
+        .enumerate()
+        .map(|(i, entry)| {
+            let track = Track {
+                offset: format.field.read(entry),
+                len: format.field.read(&entry[width..]),
+            };
+            if track
+                .offset
+                .checked_add(track.len)
+                .is_none_or(|end| end > data.len())
+            {
+                return Err(PcmError::Invalid(format!(
+                    "track {} at {}+{} ends past the {} byte input",
+                    i,
+                    track.offset,
+                    track.len,
+                    data.len()
+                )));
+            }
+            Ok(track)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(offset: usize, entries: usize) -> TocFormat {
+        TocFormat {
+            offset,
+            entries,
+            field: Field::U32Le,
+        }
+    }
+
+    #[test]
+    fn reads_offset_and_length_pairs() {
+        let mut data = [8u32, 4, 12, 2].map(u32::to_le_bytes).concat();
+        data.resize(16, 0);
+        assert_eq!(
+            read(&data, &format(0, 2)).unwrap(),
+            [Track { offset: 8, len: 4 }, Track { offset: 12, len: 2 }]
+        );
+        assert!(read(&data, &format(0, 3)).is_err());
+    }
+
+    #[test]
+    fn huge_tables_are_an_error_not_an_overflow() {
+        let data = [0u8; 16];
+        for format in [
+            format(0, usize::MAX),
+            format(usize::MAX, 1),
+            format(8, usize::MAX / 8),
+        ] {
+            assert!(matches!(read(&data, &format), Err(PcmError::Invalid(_))));
+        }
+    }
+}
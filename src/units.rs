@@ -0,0 +1,51 @@
+use crate::error::{PcmError, Result};
+
+fn split_suffix(s: &str) -> (&str, String) {
+    let at = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    (&s[..at], s[at..].trim().to_ascii_lowercase())
+}
+
+// Plain integers, or a number with a Hz, k or kHz suffix: "44.1k" is 44100.
+pub fn parse_rate(s: &str) -> Result<u32> {
+    let (number, suffix) = split_suffix(s.trim());
+    let scale = match suffix.as_str() {
+        "" | "hz" => 1.0,
+        "k" | "khz" => 1000.0,
+        _ => {
+            return Err(PcmError::Invalid(format!(
+                "unknown rate unit in {:?}, use Hz, k or kHz",
+                s
+            )))
+        }
+    };
+    let value: f64 = number
+        .parse()
+        .map_err(|_| PcmError::Invalid(format!("invalid rate {:?}", s)))?;
+    Ok((value * scale).round() as u32)
+}
+
+// Plain integers are samples; s and ms suffixes are converted at `rate`.
+pub fn parse_samples(s: &str, rate: u32) -> Result<usize> {
+    let (number, suffix) = split_suffix(s.trim());
+    if suffix.is_empty() {
+        return number
+            .parse()
+            .map_err(|_| PcmError::Invalid(format!("invalid sample count {:?}", s)));
+    }
+    let seconds = match suffix.as_str() {
+        "s" => 1.0,
+        "ms" => 0.001,
+        _ => {
+            return Err(PcmError::Invalid(format!(
+                "unknown time unit in {:?}, use s or ms",
+                s
+            )))
+        }
+    };
+    let value: f64 = number
+        .parse()
+        .map_err(|_| PcmError::Invalid(format!("invalid duration {:?}", s)))?;
+    Ok((value * seconds * rate as f64).round() as usize)
+}
@@ -0,0 +1,43 @@
+use crate::analysis::WindowFunction;
+use crate::playback::{Echo, Envelope};
+
+// How the REPL shows and plays the decoded samples. None of it changes the
+// decode or the export, so it stays out of decode::Opts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ViewOpts {
+    pub min_width: usize,
+    pub cursor: Option<usize>,
+    pub logplot: bool,
+    pub hires: bool,
+    pub autoscale: bool,
+    pub yrange: (i32, i32),
+    pub overlay: bool,
+    pub center_input: bool,
+    pub prompt_length: bool,
+    pub clip_marks: bool,
+    pub window: WindowFunction,
+    pub meter: bool,
+    pub envelope: Option<Envelope>,
+    pub echo: Option<Echo>,
+}
+
+impl Default for ViewOpts {
+    fn default() -> ViewOpts {
+        ViewOpts {
+            min_width: 8,
+            cursor: None,
+            logplot: false,
+            hires: false,
+            autoscale: true,
+            yrange: (i16::MIN as i32, i16::MAX as i32),
+            overlay: false,
+            center_input: false,
+            prompt_length: false,
+            clip_marks: true,
+            window: WindowFunction::Rect,
+            meter: false,
+            envelope: None,
+            echo: None,
+        }
+    }
+}